@@ -26,6 +26,17 @@ pub struct L1BlockRef {
 	pub time: u64,
 }
 
+/// L1BlockInfo is an L1 block with the fields that the L1 attributes of an L2 block are built from.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct L1BlockInfo {
+	pub hash: Hash,
+	pub number: u64,
+	pub parent_hash: Hash,
+	pub time: u64,
+	pub base_fee: u64,
+	pub mix_digest: Hash,
+}
+
 #[derive(Debug, Clone, Copy, Default)]
 pub struct L2BlockRef {
 	pub hash: Hash,
@@ -64,3 +75,27 @@ impl From<Header> for L1BlockRef {
 		}
 	}
 }
+
+impl From<Header> for L1BlockInfo {
+	fn from(h: Header) -> Self {
+		Self {
+			hash: h.hash_slow().into(),
+			number: h.number,
+			parent_hash: h.parent_hash.into(),
+			time: h.timestamp,
+			base_fee: h.base_fee_per_gas.unwrap_or_default(),
+			mix_digest: h.mix_hash.into(),
+		}
+	}
+}
+
+impl From<L1BlockInfo> for L1BlockRef {
+	fn from(info: L1BlockInfo) -> Self {
+		Self {
+			hash: info.hash,
+			number: info.number,
+			parent_hash: info.parent_hash,
+			time: info.time,
+		}
+	}
+}
@@ -2,19 +2,37 @@ pub mod chain_config;
 pub mod id;
 pub mod types;
 
-use ethers_core::types::Transaction;
+use id::BlockID;
+use types::{Address, Hash};
 
 #[derive(Debug)]
 pub struct L2BlockCandidate {
 	pub number: u64,
+	/// The hash of the L2 block that the candidate builds on.
+	pub parent_hash: Hash,
 	pub timestamp: u64,
-	pub transactions: Vec<Transaction>,
+	pub l1_origin: BlockID,
+	/// The encoded transactions of the batch.
+	pub transactions: Vec<Vec<u8>>,
 	// TODO: tx root
 }
 
+/// PayloadAttributes are the inputs of the engine to build an L2 block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadAttributes {
+	pub timestamp: u64,
+	pub prev_randao: Hash,
+	pub suggested_fee_recipient: Address,
+	/// The encoded transactions of the block, starting with the deposits.
+	pub transactions: Vec<Vec<u8>>,
+	pub no_tx_pool: bool,
+	pub gas_limit: u64,
+}
+
 pub mod prelude {
 	pub use crate::chain_config::RollupConfig;
 	pub use crate::id::BlockID;
+	pub use crate::id::L1BlockInfo;
 	pub use crate::id::L1BlockRef;
 	pub use crate::id::L2BlockRef;
 	pub use crate::types::Address;
@@ -23,4 +41,5 @@ pub mod prelude {
 	pub use crate::types::Receipt;
 	pub use crate::types::Transaction;
 	pub use crate::L2BlockCandidate; // TODO: remove
+	pub use crate::PayloadAttributes;
 }
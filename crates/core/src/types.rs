@@ -5,6 +5,9 @@ impl ChannelID {
 	pub fn new(id: [u8; 16]) -> Self {
 		Self(id)
 	}
+	pub fn to_vec(self) -> Vec<u8> {
+		Vec::from(self.0)
+	}
 }
 
 impl TryFrom<&[u8]> for ChannelID {
@@ -21,6 +24,9 @@ impl Address {
 	pub const fn new(v: [u8; 20]) -> Self {
 		Self(v)
 	}
+	pub fn to_vec(self) -> Vec<u8> {
+		Vec::from(self.0)
+	}
 }
 
 impl From<reth_primitives::H160> for Address {
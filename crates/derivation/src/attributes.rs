@@ -0,0 +1,245 @@
+use crate::deposit::*;
+use core::prelude::*;
+
+use ethers_core::types::{H160, H256, U256};
+use hex_literal::hex;
+use std::collections::VecDeque;
+
+/// L1_INFO_FUNC_SELECTOR is the selector of `setL1BlockValues` on the L1Block predeploy.
+const L1_INFO_FUNC_SELECTOR: [u8; 4] = hex!("015d8eb9");
+/// L1_INFO_DEPOSITOR_ADDRESS is the sender of the L1 attributes deposit.
+const L1_INFO_DEPOSITOR_ADDRESS: [u8; 20] = hex!("deaddeaddeaddeaddeaddeaddeaddeaddead0001");
+/// L1_BLOCK_ADDRESS is the address of the L1Block predeploy.
+const L1_BLOCK_ADDRESS: [u8; 20] = hex!("4200000000000000000000000000000000000015");
+/// SEQUENCER_FEE_VAULT_ADDRESS receives the priority fees of L2 blocks.
+const SEQUENCER_FEE_VAULT_ADDRESS: [u8; 20] = hex!("4200000000000000000000000000000000000011");
+
+/// The gas of the L1 attributes deposit before & after Regolith.
+const SYSTEM_TX_GAS: u64 = 150_000_000;
+const REGOLITH_SYSTEM_TX_GAS: u64 = 1_000_000;
+
+/// AttributesQueue turns the block candidates of the BatchQueue into the payload attributes that
+/// the engine executes. It keeps the L1 blocks that can be the origin of the next L2 blocks along
+/// with the user deposits in them.
+#[derive(Debug)]
+pub struct AttributesQueue {
+	config: RollupConfig,
+	epochs: VecDeque<(L1BlockInfo, Vec<DepositTx>)>,
+}
+
+impl AttributesQueue {
+	pub fn new(cfg: RollupConfig) -> Self {
+		Self {
+			config: cfg,
+			epochs: VecDeque::default(),
+		}
+	}
+
+	/// load_l1_block stores the L1 block & the user deposits in its receipts. The block is not
+	/// stored if any of its deposits are invalid.
+	pub fn load_l1_block(&mut self, info: L1BlockInfo, receipts: &[Receipt]) -> Result<(), DepositError> {
+		let deposits = user_deposits(receipts, self.config.deposit_contract_address, info.hash)?;
+		self.epochs.push_back((info, deposits));
+		Ok(())
+	}
+
+	/// build_attributes returns the payload attributes of the candidate that follows l2_head. The
+	/// transactions are the L1 attributes deposit, the user deposits of the L1 origin if this is
+	/// the first block of the epoch & then the transactions of the batch.
+	/// It returns None if the L1 origin of the candidate has not been loaded.
+	pub fn build_attributes(&mut self, l2_head: L2BlockRef, candidate: &L2BlockCandidate) -> Option<PayloadAttributes> {
+		while self.epochs.front().is_some_and(|(info, _)| info.number < l2_head.l1_origin.number) {
+			self.epochs.pop_front();
+		}
+		let (info, deposits) = self
+			.epochs
+			.iter()
+			.find(|(info, _)| info.number == candidate.l1_origin.number && info.hash == candidate.l1_origin.hash)?;
+
+		let sequence_number = sequence_number(l2_head, candidate.l1_origin);
+		let mut transactions = vec![l1_info_deposit(&self.config, info, sequence_number, candidate.timestamp).encode()];
+		if sequence_number == 0 {
+			transactions.extend(deposits.iter().map(DepositTx::encode));
+		}
+		transactions.extend(candidate.transactions.iter().cloned());
+
+		Some(PayloadAttributes {
+			timestamp: candidate.timestamp,
+			prev_randao: info.mix_digest,
+			suggested_fee_recipient: Address::new(SEQUENCER_FEE_VAULT_ADDRESS),
+			transactions,
+			no_tx_pool: true,
+			gas_limit: self.config.system_config.gas_limit,
+		})
+	}
+}
+
+/// sequence_number returns the position of the block after l2_head in the epoch of l1_origin.
+pub fn sequence_number(l2_head: L2BlockRef, l1_origin: BlockID) -> u64 {
+	if l1_origin == l2_head.l1_origin {
+		l2_head.sequence_number + 1
+	} else {
+		0
+	}
+}
+
+/// l1_info_deposit returns the deposit that sets the L1 block values on the L1Block predeploy.
+/// It is the first transaction of every L2 block.
+pub fn l1_info_deposit(cfg: &RollupConfig, info: &L1BlockInfo, sequence_number: u64, timestamp: u64) -> DepositTx {
+	let mut data = L1_INFO_FUNC_SELECTOR.to_vec();
+	data.extend_from_slice(&<[u8; 32]>::from(U256::from(info.number)));
+	data.extend_from_slice(&<[u8; 32]>::from(U256::from(info.time)));
+	data.extend_from_slice(&<[u8; 32]>::from(U256::from(info.base_fee)));
+	data.extend_from_slice(&info.hash.to_vec());
+	data.extend_from_slice(&<[u8; 32]>::from(U256::from(sequence_number)));
+	data.extend_from_slice(H256::from(H160::from_slice(&cfg.system_config.batcher_address.to_vec())).as_bytes());
+	data.extend_from_slice(&cfg.system_config.overhead.to_vec());
+	data.extend_from_slice(&cfg.system_config.scalar.to_vec());
+
+	let regolith = cfg.regolith_time.is_some_and(|t| timestamp >= t);
+	DepositTx {
+		source_hash: source_hash(L1_INFO_DEPOSIT_SOURCE_DOMAIN, info.hash, sequence_number),
+		from: H160(L1_INFO_DEPOSITOR_ADDRESS),
+		to: Some(H160(L1_BLOCK_ADDRESS)),
+		mint: U256::zero(),
+		value: U256::zero(),
+		gas: if regolith { REGOLITH_SYSTEM_TX_GAS } else { SYSTEM_TX_GAS },
+		is_system_tx: !regolith,
+		data,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use core::chain_config::GOERLI_CONFIG;
+	use ethers_core::{
+		types::{Log, TransactionReceipt},
+		utils::rlp::Rlp,
+	};
+
+	fn l1_info(number: u64) -> L1BlockInfo {
+		L1BlockInfo {
+			hash: Hash::new([number as u8; 32]),
+			number,
+			time: 1000 + 12 * number,
+			base_fee: 7,
+			mix_digest: Hash::new([0xee; 32]),
+			..Default::default()
+		}
+	}
+
+	fn candidate(number: u64, l1_origin: u64, transactions: Vec<Vec<u8>>) -> L2BlockCandidate {
+		L2BlockCandidate {
+			number,
+			parent_hash: Hash::default(),
+			timestamp: 2000 + 2 * number,
+			l1_origin: L1BlockRef::from(l1_info(l1_origin)).into(),
+			transactions,
+		}
+	}
+
+	#[test]
+	fn test_l1_info_deposit() {
+		let info = l1_info(1);
+		let tx = l1_info_deposit(&GOERLI_CONFIG, &info, 3, 0);
+		assert_eq!(tx.source_hash, source_hash(L1_INFO_DEPOSIT_SOURCE_DOMAIN, info.hash, 3));
+		assert_eq!((tx.from, tx.to), (H160(L1_INFO_DEPOSITOR_ADDRESS), Some(H160(L1_BLOCK_ADDRESS))));
+		assert_eq!((tx.gas, tx.is_system_tx), (SYSTEM_TX_GAS, true));
+
+		assert_eq!(tx.data.len(), 4 + 8 * 32);
+		let word = |i: usize| &tx.data[4 + 32 * i..4 + 32 * (i + 1)];
+		assert_eq!(tx.data[..4], hex!("015d8eb9"));
+		assert_eq!(U256::from_big_endian(word(0)), U256::from(1));
+		assert_eq!(U256::from_big_endian(word(1)), U256::from(1012));
+		assert_eq!(U256::from_big_endian(word(2)), U256::from(7));
+		assert_eq!(word(3), [1; 32]);
+		assert_eq!(U256::from_big_endian(word(4)), U256::from(3));
+		assert_eq!(word(5), hex!("0000000000000000000000007431310e026B69BFC676C0013E12A1A11411EEc9"));
+		assert_eq!(U256::from_big_endian(word(6)), U256::from(0x834));
+		assert_eq!(U256::from_big_endian(word(7)), U256::from(1_000_000));
+
+		// Regolith lowers the gas & the deposit is no longer a system transaction.
+		let tx = l1_info_deposit(&GOERLI_CONFIG, &info, 3, GOERLI_CONFIG.regolith_time.unwrap());
+		assert_eq!((tx.gas, tx.is_system_tx), (REGOLITH_SYSTEM_TX_GAS, false));
+	}
+
+	#[test]
+	fn test_build_attributes() {
+		let mut aq = AttributesQueue::new(GOERLI_CONFIG);
+		let deposit = DepositTx {
+			source_hash: H256::zero(),
+			from: H160::repeat_byte(1),
+			to: Some(H160::repeat_byte(2)),
+			mint: U256::zero(),
+			value: U256::zero(),
+			gas: 21_000,
+			is_system_tx: false,
+			data: Vec::new(),
+		};
+		// A deposit event without data, which is enough for the deposit above.
+		let mut data = [[0u8; 32]; 5].concat();
+		data[31] = 32;
+		data[63] = 73;
+		data[64 + 64 + 5..64 + 72].copy_from_slice(&hex!("005208"));
+		let receipt = TransactionReceipt {
+			status: Some(1.into()),
+			logs: vec![Log {
+				address: H160::from_slice(&GOERLI_CONFIG.deposit_contract_address.to_vec()),
+				topics: vec![
+					H256(hex!("b3813568d9991fc951961fcb4c784893574240a28925604d09fc577c55bb7c32")),
+					H256::from(deposit.from),
+					H256::from(deposit.to.unwrap()),
+					H256::zero(),
+				],
+				data: data.into(),
+				log_index: Some(0.into()),
+				..Default::default()
+			}],
+			..Default::default()
+		};
+		aq.load_l1_block(l1_info(1), &[]).unwrap();
+		aq.load_l1_block(l1_info(2), &[receipt]).unwrap();
+		let deposit = DepositTx {
+			source_hash: source_hash(USER_DEPOSIT_SOURCE_DOMAIN, l1_info(2).hash, 0),
+			..deposit
+		};
+
+		let head = L2BlockRef {
+			number: 5,
+			time: 2010,
+			l1_origin: L1BlockRef::from(l1_info(1)).into(),
+			sequence_number: 2,
+			..Default::default()
+		};
+		let batch_tx = vec![0x02, 0xaa];
+
+		// The block stays in epoch 1, so there are no user deposits.
+		let attributes = aq.build_attributes(head, &candidate(6, 1, vec![batch_tx.clone()])).unwrap();
+		assert_eq!(
+			attributes,
+			PayloadAttributes {
+				timestamp: 2012,
+				prev_randao: Hash::new([0xee; 32]),
+				suggested_fee_recipient: Address::new(SEQUENCER_FEE_VAULT_ADDRESS),
+				transactions: vec![l1_info_deposit(&GOERLI_CONFIG, &l1_info(1), 3, 2012).encode(), batch_tx.clone()],
+				no_tx_pool: true,
+				gas_limit: 25_000_000,
+			}
+		);
+
+		// The first block of epoch 2 includes its deposits after the L1 attributes deposit.
+		let attributes = aq.build_attributes(head, &candidate(6, 2, vec![batch_tx.clone()])).unwrap();
+		let l1_info_tx = l1_info_deposit(&GOERLI_CONFIG, &l1_info(2), 0, 2012).encode();
+		assert_eq!(attributes.transactions, [l1_info_tx, deposit.encode(), batch_tx]);
+		assert_eq!(Rlp::new(&attributes.transactions[1][1..]).val_at::<u64>(5).unwrap(), 21_000);
+
+		// Epochs before the origin of the safe head are forgotten.
+		let head = L2BlockRef {
+			l1_origin: L1BlockRef::from(l1_info(2)).into(),
+			..head
+		};
+		assert!(aq.build_attributes(head, &candidate(6, 1, vec![])).is_none());
+		assert_eq!(aq.epochs.len(), 1);
+	}
+}
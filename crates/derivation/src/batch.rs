@@ -1,6 +1,6 @@
 use ethers_core::{
 	types::H256, // Use ethers core H256 b/c it implements decodable
-	utils::rlp::{decode, Decodable, DecoderError, Rlp},
+	utils::rlp::{decode, encode, Decodable, DecoderError, Encodable, Rlp, RlpStream},
 };
 use eyre::Result;
 
@@ -19,10 +19,20 @@ pub struct Batch {
 	// TODO: Metadata here
 }
 
+impl Batch {
+	/// encode returns the version byte followed by the RLP encoding of the batch. This is the
+	/// inverse of decoding a Batch & is wrapped in an RLP string when it is added to a channel.
+	pub fn encode(&self) -> Vec<u8> {
+		let mut data = vec![0];
+		data.extend_from_slice(&encode(&self.batch));
+		data
+	}
+}
+
 impl Decodable for Batch {
 	fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
 		// TODO: Make this more robust
-		let first = rlp.as_raw()[0];
+		let first = *rlp.as_raw().first().ok_or(DecoderError::RlpIsTooShort)?;
 		if first != 0 {
 			return Err(DecoderError::Custom("invalid version byte"));
 		}
@@ -49,24 +59,13 @@ impl Decodable for BatchV1 {
 	}
 }
 
-pub fn parse_batches(data: Vec<u8>) -> Vec<Batch> {
-	// TODO: Truncate data to 10KB (post compression)
-	// The data we received is an RLP encoded string. Before decoding the batch itself,
-	// we need to decode the string to get the actual batch data.
-	let mut decoded_batches: Vec<Vec<u8>> = Vec::new();
-	let mut buf: &[u8] = &data;
-
-	loop {
-		let rlp = Rlp::new(buf);
-		let size = rlp.size();
-
-		match rlp.as_val() {
-			Ok(b) => {
-				decoded_batches.push(b);
-				buf = &buf[size..];
-			}
-			Err(_) => break,
-		}
+impl Encodable for BatchV1 {
+	fn rlp_append(&self, s: &mut RlpStream) {
+		s.begin_list(5);
+		s.append(&self.parent_hash);
+		s.append(&self.epoch_num);
+		s.append(&self.epoch_hash);
+		s.append(&self.timestamp);
+		s.append_list::<Vec<u8>, _>(&self.transactions);
 	}
-	decoded_batches.iter().filter_map(|b| decode(b).ok()).collect()
 }
@@ -1,18 +1,37 @@
-use ethers_core::{types::Transaction, utils::rlp::decode};
 use std::collections::{HashMap, VecDeque};
 
-use super::batch::Batch;
+use super::batch::{Batch, BatchV1};
+use super::deposit::DEPOSIT_TX_TYPE;
 use core::prelude::*;
 
+/// BatchValidity is the result of checking a batch against the L2 safe head & the buffered L1 blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchValidity {
+	/// The batch is invalid & can be dropped.
+	Drop,
+	/// The batch is valid & should be processed.
+	Accept,
+	/// More L1 blocks are needed to decide if the batch is valid.
+	Undecided,
+	/// The batch is for a later L2 block & may be valid once the safe head has advanced.
+	Future,
+}
+
+#[derive(Debug)]
+struct BatchWithInclusionBlock {
+	l1_inclusion_block: L1BlockRef,
+	batch: Batch,
+}
+
 #[derive(Debug)]
 pub struct BatchQueue {
 	l1_blocks: VecDeque<L1BlockRef>,
 	// Map batch timestamp to batches in order that they were received
-	batches: HashMap<u64, VecDeque<Batch>>,
+	batches: HashMap<u64, VecDeque<BatchWithInclusionBlock>>,
 
 	l2_block_time: u64,
-	// seq_window_size: u64,
-	// max_sequencer_drift: u64,
+	seq_window_size: u64,
+	max_sequencer_drift: u64,
 }
 
 impl BatchQueue {
@@ -21,34 +40,359 @@ impl BatchQueue {
 			l1_blocks: VecDeque::default(),
 			batches: HashMap::default(),
 			l2_block_time: cfg.l2_block_time,
-			// seq_window_size: cfg.seq_window_size,
-			// max_sequencer_drift: cfg.max_sequencer_drift,
+			seq_window_size: cfg.seq_window_size,
+			max_sequencer_drift: cfg.max_sequencer_drift,
 		}
 	}
 	pub fn load_batches(&mut self, batches: impl Iterator<Item = Batch>, l1_origin: L1BlockRef) {
 		self.l1_blocks.push_back(l1_origin);
 		for b in batches {
-			self.batches.entry(b.batch.timestamp).or_default().push_back(b);
+			self.batches
+				.entry(b.batch.timestamp)
+				.or_default()
+				.push_back(BatchWithInclusionBlock {
+					l1_inclusion_block: l1_origin,
+					batch: b,
+				});
 		}
 	}
 
+	/// get_block_candidate returns the first batch for the block after l2_head that is valid, or an
+	/// empty batch if the sequencing window has expired without one. It returns None if there is
+	/// no such batch yet or if more L1 blocks are needed to decide.
 	pub fn get_block_candidate(&mut self, l2_head: L2BlockRef) -> Option<L2BlockCandidate> {
+		// Forget the L1 blocks before the epoch of the safe head
+		while self.l1_blocks.front().is_some_and(|b| b.number < l2_head.l1_origin.number) {
+			self.l1_blocks.pop_front();
+		}
+		let epoch = *self.l1_blocks.front()?;
+		if BlockID::from(epoch) != l2_head.l1_origin {
+			return None;
+		}
+
 		let next_timestamp = l2_head.time + self.l2_block_time;
-		if let Some(candidates) = self.batches.get(&next_timestamp) {
-			#[allow(clippy::never_loop)]
-			for b in candidates {
-				// TODO: Do this step earlier
-				let txns = b.batch.transactions.iter().map(|t| decode::<Transaction>(t).unwrap()).collect();
-				self.batches.remove(&next_timestamp);
-				// TODO: deposits, seq number, transactions from batches
-				return Some(L2BlockCandidate {
-					number: l2_head.number + 1,
-					timestamp: next_timestamp,
-					transactions: txns,
-				});
+		self.batches.retain(|timestamp, _| *timestamp >= next_timestamp);
+		let mut candidates = self.batches.remove(&next_timestamp).unwrap_or_default();
+		while let Some(b) = candidates.pop_front() {
+			match self.check_batch(l2_head, &b) {
+				BatchValidity::Drop => continue,
+				BatchValidity::Accept => {
+					if !candidates.is_empty() {
+						self.batches.insert(next_timestamp, candidates);
+					}
+					return Some(self.accept(epoch, l2_head, b.batch));
+				}
+				BatchValidity::Undecided => {
+					candidates.push_front(b);
+					self.batches.insert(next_timestamp, candidates);
+					return None;
+				}
+				BatchValidity::Future => unreachable!("batch with timestamp {next_timestamp} is the next batch"),
+			}
+		}
+
+		let batch = self.empty_batch(epoch, l2_head)?;
+		Some(self.accept(epoch, l2_head, batch))
+	}
+
+	// empty_batch returns an empty batch for the block after l2_head once the sequencing window of
+	// the epoch has expired without a valid batch. The empty batches stay in the epoch until the
+	// time of the next L1 block is reached & then adopt it as their L1 origin.
+	fn empty_batch(&self, epoch: L1BlockRef, l2_head: L2BlockRef) -> Option<Batch> {
+		let l1_head = self.l1_blocks.back()?;
+		if epoch.number + self.seq_window_size > l1_head.number {
+			return None;
+		}
+		let next_epoch = self.l1_blocks.get(1)?;
+		let next_timestamp = l2_head.time + self.l2_block_time;
+		let origin = if next_timestamp < next_epoch.time { epoch } else { *next_epoch };
+		Some(Batch {
+			batch: BatchV1 {
+				parent_hash: l2_head.hash.into(),
+				epoch_num: origin.number,
+				epoch_hash: origin.hash.into(),
+				timestamp: next_timestamp,
+				transactions: Vec::new(),
+			},
+		})
+	}
+
+	// accept advances the epoch if the batch starts the next one & turns the batch into a candidate.
+	fn accept(&mut self, epoch: L1BlockRef, l2_head: L2BlockRef, batch: Batch) -> L2BlockCandidate {
+		if batch.batch.epoch_num == epoch.number + 1 {
+			self.l1_blocks.pop_front();
+		}
+		// TODO: seq number
+		L2BlockCandidate {
+			number: l2_head.number + 1,
+			parent_hash: batch.batch.parent_hash.into(),
+			timestamp: batch.batch.timestamp,
+			l1_origin: BlockID {
+				hash: batch.batch.epoch_hash.into(),
+				number: batch.batch.epoch_num,
+			},
+			transactions: batch.batch.transactions,
+		}
+	}
+
+	// check_batch applies the batch validity rules of the spec. The first buffered L1 block must be
+	// the epoch of l2_head.
+	fn check_batch(&self, l2_head: L2BlockRef, b: &BatchWithInclusionBlock) -> BatchValidity {
+		let Some(epoch) = self.l1_blocks.front() else {
+			return BatchValidity::Undecided;
+		};
+		let batch = &b.batch.batch;
+		let next_timestamp = l2_head.time + self.l2_block_time;
+		if batch.timestamp > next_timestamp {
+			return BatchValidity::Future;
+		}
+		if batch.timestamp < next_timestamp {
+			log::warn!("dropping batch with old timestamp {}, expected {next_timestamp}", batch.timestamp);
+			return BatchValidity::Drop;
+		}
+		if Hash::from(batch.parent_hash) != l2_head.hash {
+			log::warn!("dropping batch with parent hash {:?} that is not the safe head", batch.parent_hash);
+			return BatchValidity::Drop;
+		}
+		if batch.epoch_num + self.seq_window_size < b.l1_inclusion_block.number {
+			log::warn!(
+				"dropping batch that was included after the sequencing window of epoch {}",
+				batch.epoch_num
+			);
+			return BatchValidity::Drop;
+		}
+
+		// The batch can stay in the current epoch or start the next one.
+		let batch_origin = if batch.epoch_num == epoch.number {
+			epoch
+		} else if batch.epoch_num == epoch.number + 1 {
+			match self.l1_blocks.get(1) {
+				Some(next) => next,
+				None => return BatchValidity::Undecided,
+			}
+		} else {
+			log::warn!(
+				"dropping batch with epoch {} while the current epoch is {}",
+				batch.epoch_num,
+				epoch.number
+			);
+			return BatchValidity::Drop;
+		};
+		if Hash::from(batch.epoch_hash) != batch_origin.hash {
+			log::warn!("dropping batch with epoch hash {:?} that is not on the L1 chain", batch.epoch_hash);
+			return BatchValidity::Drop;
+		}
+		if batch.timestamp < batch_origin.time {
+			log::warn!(
+				"dropping batch with timestamp {} before its L1 origin at {}",
+				batch.timestamp,
+				batch_origin.time
+			);
+			return BatchValidity::Drop;
+		}
+
+		// Past the sequencer drift only empty batches are allowed, and only while the next L1 origin
+		// is still ahead of the batch so that the L2 time stays ahead of the L1 time.
+		if batch.timestamp > batch_origin.time + self.max_sequencer_drift {
+			if !batch.transactions.is_empty() {
+				log::warn!("dropping batch with transactions past the sequencer drift");
+				return BatchValidity::Drop;
+			}
+			if epoch.number == batch_origin.number {
+				match self.l1_blocks.get(1) {
+					None => return BatchValidity::Undecided,
+					Some(next) if batch.timestamp >= next.time => {
+						log::warn!("dropping batch past the sequencer drift that could have adopted the next L1 origin");
+						return BatchValidity::Drop;
+					}
+					Some(_) => {}
+				}
+			}
+		}
+
+		for (i, tx) in batch.transactions.iter().enumerate() {
+			if tx.is_empty() {
+				log::warn!("dropping batch with empty transaction {i}");
+				return BatchValidity::Drop;
+			}
+			if tx[0] == DEPOSIT_TX_TYPE {
+				log::warn!("dropping batch with deposit transaction {i}");
+				return BatchValidity::Drop;
 			}
 		}
+		BatchValidity::Accept
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use core::chain_config::GOERLI_CONFIG;
+	use ethers_core::types::H256;
+
+	const SAFE_HEAD_HASH: [u8; 32] = [0xaa; 32];
+
+	// l1 returns an L1 block that is 12 seconds after the previous one.
+	fn l1(number: u64) -> L1BlockRef {
+		L1BlockRef {
+			hash: Hash::new([number as u8; 32]),
+			number,
+			time: 880 + 12 * number,
+			..Default::default()
+		}
+	}
+
+	fn safe_head() -> L2BlockRef {
+		L2BlockRef {
+			hash: Hash::new(SAFE_HEAD_HASH),
+			number: 5,
+			time: 1000,
+			l1_origin: l1(10).into(),
+			..Default::default()
+		}
+	}
+
+	fn batch(epoch: L1BlockRef, timestamp: u64, transactions: Vec<Vec<u8>>) -> Batch {
+		Batch {
+			batch: BatchV1 {
+				parent_hash: H256::from(SAFE_HEAD_HASH),
+				epoch_num: epoch.number,
+				epoch_hash: epoch.hash.into(),
+				timestamp,
+				transactions,
+			},
+		}
+	}
+
+	fn queue(l1_blocks: &[L1BlockRef]) -> BatchQueue {
+		let mut bq = BatchQueue::new(GOERLI_CONFIG);
+		bq.l1_blocks.extend(l1_blocks);
+		bq
+	}
 
-		None
+	fn check(bq: &BatchQueue, batch: Batch, l1_inclusion_block: L1BlockRef) -> BatchValidity {
+		bq.check_batch(safe_head(), &BatchWithInclusionBlock { l1_inclusion_block, batch })
+	}
+
+	#[test]
+	fn test_check_batch() {
+		let bq = queue(&[l1(10)]);
+		let tx = vec![vec![0x02, 0x01]];
+		assert_eq!(check(&bq, batch(l1(10), 1002, tx.clone()), l1(10)), BatchValidity::Accept);
+		assert_eq!(check(&bq, batch(l1(10), 1004, tx.clone()), l1(10)), BatchValidity::Future);
+		assert_eq!(check(&bq, batch(l1(10), 1000, tx.clone()), l1(10)), BatchValidity::Drop);
+
+		let mut b = batch(l1(10), 1002, tx.clone());
+		b.batch.parent_hash = H256::zero();
+		assert_eq!(check(&bq, b, l1(10)), BatchValidity::Drop);
+		let mut b = batch(l1(10), 1002, tx.clone());
+		b.batch.epoch_hash = H256::zero();
+		assert_eq!(check(&bq, b, l1(10)), BatchValidity::Drop);
+
+		// The epoch must be the current one or the next, which needs the next L1 block.
+		assert_eq!(check(&bq, batch(l1(9), 1002, tx.clone()), l1(10)), BatchValidity::Drop);
+		assert_eq!(check(&bq, batch(l1(11), 1002, tx.clone()), l1(11)), BatchValidity::Undecided);
+		assert_eq!(check(&bq, batch(l1(12), 1002, tx.clone()), l1(12)), BatchValidity::Drop);
+		let bq = queue(&[l1(10), l1(11)]);
+		assert_eq!(check(&bq, batch(l1(11), 1012, tx.clone()), l1(11)), BatchValidity::Future);
+		assert_eq!(check(&bq, batch(l1(11), 1002, tx.clone()), l1(11)), BatchValidity::Drop);
+
+		// The batch must be included within the sequencing window.
+		let late = L1BlockRef {
+			number: 10 + GOERLI_CONFIG.seq_window_size + 1,
+			..l1(11)
+		};
+		assert_eq!(check(&bq, batch(l1(10), 1002, tx.clone()), late), BatchValidity::Drop);
+
+		assert_eq!(check(&bq, batch(l1(10), 1002, vec![vec![]]), l1(10)), BatchValidity::Drop);
+		assert_eq!(
+			check(&bq, batch(l1(10), 1002, vec![vec![DEPOSIT_TX_TYPE]]), l1(10)),
+			BatchValidity::Drop
+		);
+	}
+
+	#[test]
+	fn test_check_batch_sequencer_drift() {
+		let drift = GOERLI_CONFIG.max_sequencer_drift;
+		let head = L2BlockRef {
+			time: 1000 + drift,
+			..safe_head()
+		};
+		let check = |l1_blocks: &[L1BlockRef], transactions: Vec<Vec<u8>>| {
+			let b = BatchWithInclusionBlock {
+				l1_inclusion_block: l1(10),
+				batch: batch(l1(10), head.time + 2, transactions),
+			};
+			queue(l1_blocks).check_batch(head, &b)
+		};
+		let next = |time: u64| L1BlockRef { time, ..l1(11) };
+
+		// Batches with transactions can not go past the drift.
+		assert_eq!(check(&[l1(10)], vec![vec![0x02]]), BatchValidity::Drop);
+		// Empty batches can, as long as the next L1 origin is after them.
+		assert_eq!(check(&[l1(10)], vec![]), BatchValidity::Undecided);
+		assert_eq!(check(&[l1(10), next(head.time + 3)], vec![]), BatchValidity::Accept);
+		assert_eq!(check(&[l1(10), next(head.time + 2)], vec![]), BatchValidity::Drop);
+	}
+
+	#[test]
+	fn test_get_block_candidate() {
+		let mut bq = queue(&[]);
+		// next is an L1 block that the next L2 block can already adopt as its origin.
+		let next = L1BlockRef { time: 1002, ..l1(11) };
+		let mut invalid = batch(l1(10), 1002, vec![vec![0x02, 0x01]]);
+		invalid.batch.parent_hash = H256::zero();
+		let batches = [invalid, batch(next, 1002, vec![vec![0x02, 0x01]]), batch(l1(10), 1002, vec![])];
+		bq.load_batches(batches.into_iter(), l1(10));
+
+		// The second batch starts the next epoch, so it waits for the next L1 block.
+		assert!(bq.get_block_candidate(safe_head()).is_none());
+		bq.load_batches(std::iter::empty(), next);
+		let candidate = bq.get_block_candidate(safe_head()).unwrap();
+		assert_eq!((candidate.number, candidate.timestamp, candidate.l1_origin), (6, 1002, next.into()));
+		// The transactions are passed on as they are in the batch.
+		assert_eq!(candidate.transactions, [vec![0x02, 0x01]]);
+		assert_eq!(bq.l1_blocks.iter().map(|b| b.number).collect::<Vec<_>>(), [11]);
+
+		// The rest of the batches for the block are dropped once the safe head moves past it.
+		let head = L2BlockRef {
+			hash: Hash::new(SAFE_HEAD_HASH),
+			number: 6,
+			time: 1002,
+			l1_origin: l1(11).into(),
+			..Default::default()
+		};
+		assert!(bq.get_block_candidate(head).is_none());
+		assert!(bq.batches.is_empty());
+	}
+
+	#[test]
+	fn test_empty_batches() {
+		let mut bq = BatchQueue::new(RollupConfig {
+			seq_window_size: 2,
+			..GOERLI_CONFIG
+		});
+		let mut head = safe_head();
+		bq.load_batches(std::iter::empty(), l1(10));
+		bq.load_batches(std::iter::empty(), l1(11));
+		assert!(bq.get_block_candidate(head).is_none());
+
+		// Once the window of epoch 10 has expired, empty blocks are derived until the L2 time
+		// reaches the time of the next L1 block, which is adopted as the L1 origin.
+		bq.load_batches(std::iter::empty(), l1(12));
+		let mut origins = Vec::new();
+		while let Some(candidate) = bq.get_block_candidate(head) {
+			assert_eq!((candidate.number, candidate.timestamp), (head.number + 1, head.time + 2));
+			assert!(candidate.transactions.is_empty());
+			origins.push(candidate.l1_origin.number);
+			head = L2BlockRef {
+				number: candidate.number,
+				time: candidate.timestamp,
+				l1_origin: candidate.l1_origin,
+				..head
+			};
+		}
+		assert_eq!(origins, [10, 10, 10, 10, 10, 11]);
+		assert_eq!(head.l1_origin, l1(11).into());
 	}
 }
@@ -10,8 +10,8 @@ pub struct Channel {
 	size: u64,
 	highest_frame: u16,
 	end_frame: Option<u16>,
-	lowest_l1_block: BlockID,
-	highest_l1_block: BlockID,
+	// open_l1_block is the L1 block in which the first frame of the channel was seen.
+	open_l1_block: BlockID,
 }
 
 impl Channel {
@@ -22,12 +22,11 @@ impl Channel {
 			size: 0,
 			highest_frame: 0,
 			end_frame: None,
-			lowest_l1_block: l1_block,
-			highest_l1_block: l1_block,
+			open_l1_block: l1_block,
 		}
 	}
 
-	pub fn add_frame(&mut self, frame: Frame, l1_block: BlockID) {
+	pub fn add_frame(&mut self, frame: Frame) {
 		// These checks are specififed & cannot be changed without a HF
 		if self.id != frame.id
 			|| self.closed() && frame.is_last
@@ -40,7 +39,7 @@ impl Channel {
 		if frame.is_last {
 			self.end_frame = Some(frame.number);
 			// Prune higher frames if this is the closing frame
-			if frame.number > self.highest_frame {
+			if frame.number < self.highest_frame {
 				self.frames.drain_filter(|k, _| *k > frame.number).for_each(|(_, v)| {
 					self.size -= v.size();
 				});
@@ -49,7 +48,6 @@ impl Channel {
 		}
 
 		self.highest_frame = max(self.highest_frame, frame.number);
-		self.highest_l1_block = max(self.highest_l1_block, l1_block);
 		self.size += frame.size();
 		self.frames.insert(frame.number, frame);
 	}
@@ -72,9 +70,10 @@ impl Channel {
 		self.end_frame.is_some()
 	}
 
-	pub fn is_timed_out(&self, timeout: u64) -> bool {
-		// TODO: > or >= here?
-		self.highest_l1_block.number - self.lowest_l1_block.number > timeout
+	/// is_timed_out returns true once more than timeout L1 blocks have passed since the channel
+	/// was opened, at which point it can no longer be read.
+	pub fn is_timed_out(&self, l1_block: BlockID, timeout: u64) -> bool {
+		self.open_l1_block.number + timeout < l1_block.number
 	}
 
 	pub fn size(&self) -> u64 {
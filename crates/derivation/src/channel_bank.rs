@@ -7,12 +7,23 @@ use std::collections::{HashMap, VecDeque};
 
 const MAX_CHANNEL_BANK_SIZE: u64 = 100_000_000;
 
+/// DropReason is why a channel was removed from the channel bank without being read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+	/// The channel was not ready before its timeout.
+	TimedOut,
+	/// The channel bank was over MAX_CHANNEL_BANK_SIZE & this was the oldest channel.
+	Pruned,
+}
+
 #[derive(Debug)]
 /// ChannelBank stores all pending transactions
 pub struct ChannelBank {
 	channels_map: HashMap<ChannelID, Channel>,
 	channels_by_creation: VecDeque<ChannelID>,
 	channel_timeout: u64,
+	max_size: u64,
+	dropped: Vec<(ChannelID, DropReason)>,
 }
 
 impl ChannelBank {
@@ -21,9 +32,12 @@ impl ChannelBank {
 			channels_map: HashMap::default(),
 			channels_by_creation: VecDeque::default(),
 			channel_timeout: cfg.channel_timeout,
+			max_size: MAX_CHANNEL_BANK_SIZE,
+			dropped: Vec::default(),
 		}
 	}
-	/// load_frame adds a frame to the channel bank.
+	/// load_frame adds a frame that was included in l1_block to the channel bank. Frames for a
+	/// channel that has timed out are ignored.
 	/// The caller must maintain the invariant that get_ready_channel is called until there
 	/// are no more ready channels before adding more frames.
 	/// This function will panic (via assert) if this invariant is not maintained.
@@ -33,26 +47,35 @@ impl ChannelBank {
 			"Specs Violation: must pull data before loading more in the channel bank"
 		);
 
-		self.channels_map
-			.entry(frame.id)
-			.or_insert_with(|| {
-				self.channels_by_creation.push_back(frame.id);
-				Channel::new(frame.id, l1_block)
-			})
-			.add_frame(frame, l1_block);
+		let channel = self.channels_map.entry(frame.id).or_insert_with(|| {
+			self.channels_by_creation.push_back(frame.id);
+			Channel::new(frame.id, l1_block)
+		});
+		if channel.is_timed_out(l1_block, self.channel_timeout) {
+			return;
+		}
+		channel.add_frame(frame);
 		self.prune();
 	}
 
-	/// get_ready_channel returns the first ready channel.
-	pub fn get_ready_channel(&mut self) -> Option<Channel> {
-		// TODO: this should be a while loop. See if there is a test or fuzzing to catch this.
-		if self.peek()?.is_ready() {
-			let ch = self.remove().unwrap();
-			if !ch.is_timed_out(self.channel_timeout) {
-				return Some(ch);
+	/// get_ready_channel returns the first channel if it is ready as of l1_block. Timed out
+	/// channels at the front of the queue are dropped first, so a later channel can be returned.
+	pub fn get_ready_channel(&mut self, l1_block: BlockID) -> Option<Channel> {
+		loop {
+			let ch = self.peek()?;
+			if ch.is_timed_out(l1_block, self.channel_timeout) {
+				self.drop_front(DropReason::TimedOut);
+			} else if ch.is_ready() {
+				return self.remove();
+			} else {
+				return None;
 			}
 		}
-		None
+	}
+
+	/// take_dropped returns the channels that were dropped since it was last called.
+	pub fn take_dropped(&mut self) -> Vec<(ChannelID, DropReason)> {
+		std::mem::take(&mut self.dropped)
 	}
 
 	fn peek(&self) -> Option<&Channel> {
@@ -63,9 +86,15 @@ impl ChannelBank {
 		self.channels_map.remove(&self.channels_by_creation.pop_front()?)
 	}
 
+	fn drop_front(&mut self, reason: DropReason) {
+		let id = *self.channels_by_creation.front().expect("Should have a channel to drop");
+		self.remove();
+		self.dropped.push((id, reason));
+	}
+
 	fn prune(&mut self) {
-		while self.total_size() > MAX_CHANNEL_BANK_SIZE {
-			self.remove().expect("Should have removed a channel");
+		while self.total_size() > self.max_size {
+			self.drop_front(DropReason::Pruned);
 		}
 	}
 
@@ -86,7 +115,7 @@ impl<'a, I: Iterator<Item = Frame>> Iterator for ChannelBankAdapter<'a, I> {
 
 	fn next(&mut self) -> Option<Self::Item> {
 		loop {
-			if let Some(ch) = self.cb.get_ready_channel() {
+			if let Some(ch) = self.cb.get_ready_channel(self.l1_block) {
 				return Some(ch);
 			}
 			self.cb.load_frame(self.inner.next()?, self.l1_block);
@@ -108,3 +137,163 @@ pub trait ChannelBankAdapterIteratorExt<'a, I>: Iterator<Item = Frame> + Sized {
 }
 
 impl<'a, I: Iterator<Item = Frame>> ChannelBankAdapterIteratorExt<'a, I> for I {}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// frame parses the "id:number:data" format of op-node's channel bank tests. The id is a single
+	// character & a trailing "!" marks the closing frame.
+	fn frame(s: &str) -> Frame {
+		let mut parts = s.splitn(3, ':');
+		let id = parts.next().unwrap().as_bytes()[0];
+		let number = parts.next().unwrap().parse().unwrap();
+		let data = parts.next().unwrap();
+		Frame {
+			id: ChannelID::new([id; 16]),
+			number,
+			data: data.trim_end_matches('!').as_bytes().to_vec(),
+			is_last: data.ends_with('!'),
+		}
+	}
+
+	fn block(number: u64) -> BlockID {
+		BlockID {
+			number,
+			..Default::default()
+		}
+	}
+
+	fn bank() -> ChannelBank {
+		ChannelBank {
+			channels_map: HashMap::default(),
+			channels_by_creation: VecDeque::default(),
+			channel_timeout: 10,
+			max_size: MAX_CHANNEL_BANK_SIZE,
+			dropped: Vec::default(),
+		}
+	}
+
+	// run loads each frame at its L1 block & returns the data of every channel that is read, in
+	// the same way that ChannelBankAdapter drains the bank before loading the next frame.
+	fn run(cb: &mut ChannelBank, frames: &[(u64, &str)]) -> Vec<String> {
+		let mut out = Vec::new();
+		for (number, f) in frames {
+			while let Some(ch) = cb.get_ready_channel(block(*number)) {
+				out.push(String::from_utf8(ch.data().collect()).unwrap());
+			}
+			cb.load_frame(frame(f), block(*number));
+		}
+		let last = frames.last().map_or(0, |(number, _)| *number);
+		while let Some(ch) = cb.get_ready_channel(block(last)) {
+			out.push(String::from_utf8(ch.data().collect()).unwrap());
+		}
+		out
+	}
+
+	#[test]
+	fn test_channel_bank_simple() {
+		let mut cb = bank();
+		let out = run(&mut cb, &[(0, "a:0:first"), (0, "a:2:third!"), (0, "a:1:second")]);
+		assert_eq!(out, ["firstsecondthird"]);
+		assert!(cb.get_ready_channel(block(0)).is_none());
+		assert!(cb.take_dropped().is_empty());
+	}
+
+	#[test]
+	fn test_channel_bank_duplicates() {
+		let mut cb = bank();
+		let out = run(
+			&mut cb,
+			&[
+				(0, "a:0:first"),
+				(0, "a:2:third!"),
+				(0, "a:0:altfirst"),
+				(0, "a:2:altthird!"),
+				(0, "a:1:second"),
+			],
+		);
+		assert_eq!(out, ["firstsecondthird"]);
+	}
+
+	#[test]
+	fn test_channel_bank_interleaved() {
+		let mut cb = bank();
+		let out = run(
+			&mut cb,
+			&[
+				(0, "a:0:first"),
+				(0, "b:2:board!"),
+				(0, "c:0:cake!"),
+				(0, "b:0:bread"),
+				(0, "b:1:bake"),
+				(0, "a:1:last!"),
+			],
+		);
+		// b & c are ready before a, but channels are read in the order they were opened.
+		assert_eq!(out, ["firstlast", "breadbakeboard", "cake"]);
+	}
+
+	#[test]
+	fn test_channel_bank_closing_frame_prunes() {
+		let mut cb = bank();
+		let out = run(
+			&mut cb,
+			&[
+				(0, "a:0:first"),
+				(0, "a:3:extra"),
+				(0, "a:1:last!"),
+				(0, "a:2:ignored"),
+				(0, "a:1:again!"),
+			],
+		);
+		assert_eq!(out, ["firstlast"]);
+	}
+
+	#[test]
+	fn test_channel_bank_timeout() {
+		let mut cb = bank();
+		let a = ChannelID::new([b'a'; 16]);
+		// a times out at block 11, which lets b be read.
+		let out = run(&mut cb, &[(0, "a:0:first"), (5, "b:0:bold!"), (11, "c:0:cake!")]);
+		assert_eq!(out, ["bold", "cake"]);
+		assert_eq!(cb.take_dropped(), [(a, DropReason::TimedOut)]);
+		assert!(cb.take_dropped().is_empty());
+
+		// A late frame of a opens a new channel, which times out as well.
+		let out = run(&mut cb, &[(20, "a:1:second!"), (30, "d:0:dash"), (31, "d:1:dot!")]);
+		assert_eq!(out, ["dashdot"]);
+		assert_eq!(cb.take_dropped(), [(a, DropReason::TimedOut)]);
+	}
+
+	#[test]
+	fn test_channel_bank_timeout_front() {
+		let mut cb = bank();
+		// a blocks the ready channel b until it times out.
+		let out = run(&mut cb, &[(0, "a:0:first"), (1, "b:0:bold!"), (10, "c:0:cake")]);
+		assert!(out.is_empty());
+		assert_eq!(
+			cb.get_ready_channel(block(11)).map(|ch| ch.data().collect()),
+			Some(b"bold".to_vec())
+		);
+		assert_eq!(cb.take_dropped(), [(ChannelID::new([b'a'; 16]), DropReason::TimedOut)]);
+	}
+
+	#[test]
+	fn test_channel_bank_pruned() {
+		// The bank fits two of the frames below, but not three.
+		let mut cb = ChannelBank {
+			max_size: 2 * frame("a:0:first").size() + 1,
+			..bank()
+		};
+		let out = run(&mut cb, &[(0, "a:0:first"), (0, "b:0:bread")]);
+		assert!(out.is_empty());
+		assert!(cb.take_dropped().is_empty());
+		// c takes the bank over its size limit, so the oldest channel a is pruned.
+		cb.load_frame(frame("c:0:cakes!"), block(0));
+		assert_eq!(cb.take_dropped(), [(ChannelID::new([b'a'; 16]), DropReason::Pruned)]);
+		// b is still open & blocks c, which is ready.
+		assert!(cb.get_ready_channel(block(0)).is_none());
+		assert_eq!(cb.channels_by_creation, [ChannelID::new([b'b'; 16]), ChannelID::new([b'c'; 16])]);
+	}
+}
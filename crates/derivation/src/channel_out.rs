@@ -0,0 +1,241 @@
+use crate::batch::Batch;
+use crate::channel_reader::MAX_RLP_BYTES_PER_CHANNEL;
+use crate::frame::{Frame, FRAME_OVERHEAD, MAX_DATA_LEN};
+use core::types::ChannelID;
+
+use ethers_core::utils::rlp::encode;
+use flate2::{write::ZlibEncoder, Compression};
+use std::fmt::Display;
+use std::io::Write;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChannelOutError {
+	/// The maximum frame size does not leave room for any data after FRAME_OVERHEAD.
+	FrameTooSmall(usize),
+	/// The channel needs more frames than a u16 frame number can count.
+	TooManyFrames(usize),
+	/// The batch would take the channel past MAX_RLP_BYTES_PER_CHANNEL, so the ChannelReader would
+	/// not read it.
+	TooManyRlpBytes(u64),
+}
+
+impl Display for ChannelOutError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ChannelOutError::FrameTooSmall(size) => {
+				write!(f, "max frame size {size} must be larger than the frame overhead {FRAME_OVERHEAD}")
+			}
+			ChannelOutError::TooManyFrames(n) => {
+				write!(f, "channel needs {n} frames, which is more than {}", u16::MAX as usize + 1)
+			}
+			ChannelOutError::TooManyRlpBytes(len) => {
+				write!(
+					f,
+					"channel of {len} RLP bytes exceeds {MAX_RLP_BYTES_PER_CHANNEL} bytes per channel"
+				)
+			}
+		}
+	}
+}
+
+impl std::error::Error for ChannelOutError {}
+
+/// ChannelOut builds a channel from batches. This is the reverse of the ChannelBank & ChannelReader:
+/// each batch is RLP encoded & compressed as it is added, and the channel is split into frames once
+/// it is closed.
+pub struct ChannelOut {
+	id: ChannelID,
+	compressor: ZlibEncoder<Vec<u8>>,
+	rlp_len: u64,
+	limit: u64,
+}
+
+impl ChannelOut {
+	pub fn new(id: ChannelID) -> Self {
+		Self::with_limit(id, MAX_RLP_BYTES_PER_CHANNEL)
+	}
+
+	fn with_limit(id: ChannelID, limit: u64) -> Self {
+		Self {
+			id,
+			compressor: ZlibEncoder::new(Vec::new(), Compression::default()),
+			rlp_len: 0,
+			limit,
+		}
+	}
+
+	/// add_batch appends the batch to the channel. It fails without adding the batch if the
+	/// uncompressed channel would be larger than MAX_RLP_BYTES_PER_CHANNEL.
+	pub fn add_batch(&mut self, batch: &Batch) -> Result<(), ChannelOutError> {
+		let rlp = encode(&batch.encode());
+		let rlp_len = self.rlp_len + rlp.len() as u64;
+		if rlp_len > self.limit {
+			return Err(ChannelOutError::TooManyRlpBytes(rlp_len));
+		}
+		self.compressor.write_all(&rlp).expect("writing to a Vec does not fail");
+		self.rlp_len = rlp_len;
+		Ok(())
+	}
+
+	/// close finishes compressing the channel & splits it into frames. Each frame is at most
+	/// max_frame_size bytes when encoded. It fails if max_frame_size is not larger than
+	/// FRAME_OVERHEAD or if the frames can not be numbered by a u16.
+	pub fn close(self, max_frame_size: usize) -> Result<Vec<Frame>, ChannelOutError> {
+		if max_frame_size <= FRAME_OVERHEAD {
+			return Err(ChannelOutError::FrameTooSmall(max_frame_size));
+		}
+		let data = self.compressor.finish().expect("writing to a Vec does not fail");
+		let frame_data_len = (max_frame_size - FRAME_OVERHEAD).min(MAX_DATA_LEN as usize);
+		let chunks: Vec<_> = data.chunks(frame_data_len).collect();
+		if chunks.len() > u16::MAX as usize + 1 {
+			return Err(ChannelOutError::TooManyFrames(chunks.len()));
+		}
+		Ok(chunks
+			.iter()
+			.enumerate()
+			.map(|(i, chunk)| Frame {
+				id: self.id,
+				number: i as u16,
+				data: chunk.to_vec(),
+				is_last: i == chunks.len() - 1,
+			})
+			.collect())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::batch::BatchV1;
+	use crate::channel_reader::ChannelReader;
+	use crate::derivation::Derivation;
+	use crate::frame::{frames_to_tx_data, parse_frames};
+	use core::chain_config::GOERLI_CONFIG;
+	use core::prelude::*;
+	use ethers_core::types::H256;
+
+	// batch returns a batch in the first epoch of Goerli.
+	fn batch(parent_hash: H256, timestamp: u64, transactions: Vec<Vec<u8>>) -> Batch {
+		Batch {
+			batch: BatchV1 {
+				parent_hash,
+				epoch_num: GOERLI_CONFIG.l1_genesis.number,
+				epoch_hash: GOERLI_CONFIG.l1_genesis.hash.into(),
+				timestamp,
+				transactions,
+			},
+		}
+	}
+
+	// noise returns bytes that do not compress, so the channel is split over several frames.
+	fn noise(len: usize, mut seed: u64) -> Vec<u8> {
+		(0..len).map(|_| {
+			seed ^= seed << 13;
+			seed ^= seed >> 7;
+			seed ^= seed << 17;
+			seed as u8
+		})
+		.collect()
+	}
+
+	#[test]
+	fn test_channel_out_frames() {
+		let id = ChannelID::new([7; 16]);
+		let mut channel = ChannelOut::new(id);
+		channel.add_batch(&batch(H256::zero(), 10, vec![noise(300, 1)])).unwrap();
+		channel.add_batch(&batch(H256::zero(), 12, vec![noise(200, 2), noise(100, 3)]))
+			.unwrap();
+		let frames = channel.close(100).unwrap();
+
+		assert!(frames.len() > 1);
+		for (i, frame) in frames.iter().enumerate() {
+			let mut encoded = Vec::new();
+			frame.encode(&mut encoded);
+			assert!(encoded.len() <= 100);
+			assert_eq!((frame.id, frame.number, frame.is_last), (id, i as u16, i == frames.len() - 1));
+		}
+		assert_eq!(parse_frames(&frames_to_tx_data(&frames)).unwrap(), frames);
+	}
+
+	#[test]
+	fn test_channel_out_errors() {
+		assert_eq!(
+			ChannelOut::new(ChannelID::default()).close(FRAME_OVERHEAD),
+			Err(ChannelOutError::FrameTooSmall(FRAME_OVERHEAD))
+		);
+
+		// One byte of data per frame needs more frame numbers than there are.
+		let mut channel = ChannelOut::new(ChannelID::default());
+		channel.add_batch(&batch(H256::zero(), 10, vec![noise(70_000, 4)])).unwrap();
+		assert!(matches!(channel.close(FRAME_OVERHEAD + 1), Err(ChannelOutError::TooManyFrames(n)) if n > 70_000));
+	}
+
+	#[test]
+	fn test_channel_out_rlp_limit() {
+		let b = batch(H256::zero(), 10, vec![noise(100, 5)]);
+		let len = encode(&b.encode()).len() as u64;
+		let mut channel = ChannelOut::with_limit(ChannelID::default(), 2 * len);
+		channel.add_batch(&b).unwrap();
+		channel.add_batch(&b).unwrap();
+		assert_eq!(channel.add_batch(&b), Err(ChannelOutError::TooManyRlpBytes(3 * len)));
+
+		// The batch that went over the limit is not in the channel.
+		let frames = channel.close(MAX_DATA_LEN as usize).unwrap();
+		let data: Vec<u8> = frames.iter().flat_map(|f| f.data.clone()).collect();
+		assert_eq!(
+			ChannelReader::new(&data[..])
+				.map(|b| b.unwrap().batch.timestamp)
+				.collect::<Vec<_>>(),
+			[10, 10]
+		);
+	}
+
+	#[test]
+	fn test_channel_out_round_trip() {
+		let cfg = GOERLI_CONFIG;
+		let mut channel = ChannelOut::new(ChannelID::new([9; 16]));
+		// The hash of the first block is only known once it is executed, so pick one.
+		let block_hash = H256::repeat_byte(1);
+		channel.add_batch(&batch(cfg.l2_genesis.hash.into(), cfg.l2_genesis_time + 2, vec![]))
+			.unwrap();
+		channel.add_batch(&batch(block_hash, cfg.l2_genesis_time + 4, vec![])).unwrap();
+		let frames = channel.close(FRAME_OVERHEAD + 40).unwrap();
+		assert!(frames.len() > 1);
+
+		// Spread the frames over one transaction each.
+		let transactions = frames
+			.chunks(1)
+			.map(|frames| Transaction {
+				hash: Hash::default(),
+				to: Some(cfg.batch_inbox_address),
+				from: cfg.system_config.batcher_address,
+				input: frames_to_tx_data(frames),
+			})
+			.collect();
+		let mut derivation = Derivation::new(cfg);
+		let l1_block = L1BlockInfo {
+			number: cfg.l1_genesis.number,
+			hash: cfg.l1_genesis.hash,
+			time: cfg.l2_genesis_time,
+			..Default::default()
+		};
+		derivation.load_l1_data(l1_block, transactions, Vec::new()).unwrap();
+
+		let mut l2_head = L2BlockRef {
+			hash: cfg.l2_genesis.hash,
+			time: cfg.l2_genesis_time,
+			l1_origin: cfg.l1_genesis,
+			..Default::default()
+		};
+		for timestamp in [cfg.l2_genesis_time + 2, cfg.l2_genesis_time + 4] {
+			// The batches are empty, so the block only has the L1 attributes deposit.
+			let attributes = derivation.next_l2_attributes(l2_head).unwrap();
+			assert_eq!(attributes.timestamp, timestamp);
+			assert_eq!(attributes.transactions.len(), 1);
+			l2_head.hash = block_hash.into();
+			l2_head.time = attributes.timestamp;
+			l2_head.sequence_number += 1;
+		}
+		assert!(derivation.next_l2_attributes(l2_head).is_none());
+	}
+}
@@ -0,0 +1,216 @@
+use crate::batch::Batch;
+
+use ethers_core::utils::rlp::{decode, DecoderError};
+use flate2::read::ZlibDecoder;
+use std::fmt::Display;
+use std::io::{ErrorKind, Read, Take};
+
+/// MAX_RLP_BYTES_PER_CHANNEL is the maximum number of bytes that are read from a decompressed
+/// channel. Batches past this limit are ignored.
+pub const MAX_RLP_BYTES_PER_CHANNEL: u64 = 10_000_000;
+
+#[derive(Debug)]
+pub enum ChannelReaderError {
+	/// The channel is not valid zlib data.
+	Decompress(std::io::Error),
+	/// The channel ends in the middle of a batch.
+	Truncated,
+	/// The batch is larger than the rest of MAX_RLP_BYTES_PER_CHANNEL.
+	TooLarge(u64),
+	/// The batch is not wrapped in an RLP string.
+	NotAString(u8),
+	/// The batch could not be decoded.
+	InvalidBatch(DecoderError),
+}
+
+impl Display for ChannelReaderError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ChannelReaderError::Decompress(err) => write!(f, "failed to decompress channel: {err}"),
+			ChannelReaderError::Truncated => write!(f, "channel ends in the middle of a batch"),
+			ChannelReaderError::TooLarge(len) => {
+				write!(f, "batch of {len} bytes exceeds {MAX_RLP_BYTES_PER_CHANNEL} bytes per channel")
+			}
+			ChannelReaderError::NotAString(b) => write!(f, "batch does not start with an RLP string: {b:#x}"),
+			ChannelReaderError::InvalidBatch(err) => write!(f, "invalid batch: {err}"),
+		}
+	}
+}
+
+impl std::error::Error for ChannelReaderError {}
+
+impl From<std::io::Error> for ChannelReaderError {
+	fn from(err: std::io::Error) -> Self {
+		match err.kind() {
+			ErrorKind::UnexpectedEof => ChannelReaderError::Truncated,
+			_ => ChannelReaderError::Decompress(err),
+		}
+	}
+}
+
+/// ChannelReader decompresses a channel as a stream & decodes the batches in it. Only the first
+/// MAX_RLP_BYTES_PER_CHANNEL decompressed bytes are read. The reader stops after the first error,
+/// so the batches before a corrupt tail are kept & the rest of the channel is dropped.
+pub struct ChannelReader<R: Read> {
+	inner: Take<ZlibDecoder<R>>,
+	done: bool,
+}
+
+impl<R: Read> ChannelReader<R> {
+	pub fn new(r: R) -> Self {
+		Self::with_limit(r, MAX_RLP_BYTES_PER_CHANNEL)
+	}
+
+	fn with_limit(r: R, limit: u64) -> Self {
+		Self {
+			inner: ZlibDecoder::new(r).take(limit),
+			done: false,
+		}
+	}
+
+	// next_batch reads the RLP string that wraps the next batch & decodes it. It returns None at
+	// the end of the channel or once the limit is reached.
+	fn next_batch(&mut self) -> Result<Option<Batch>, ChannelReaderError> {
+		let mut prefix = [0u8];
+		if self.inner.read(&mut prefix)? == 0 {
+			return Ok(None);
+		}
+		let len = match prefix[0] {
+			b @ 0x00..=0x7f => return Err(ChannelReaderError::NotAString(b)),
+			b @ 0x80..=0xb7 => (b - 0x80) as u64,
+			b @ 0xb8..=0xbf => {
+				let mut len = [0u8; 8];
+				let len_of_len = (b - 0xb7) as usize;
+				self.inner.read_exact(&mut len[8 - len_of_len..])?;
+				u64::from_be_bytes(len)
+			}
+			b => return Err(ChannelReaderError::NotAString(b)),
+		};
+		// Check the length before reading so that a large length does not allocate.
+		if len > self.inner.limit() {
+			return Err(ChannelReaderError::TooLarge(len));
+		}
+		let mut data = vec![0; len as usize];
+		self.inner.read_exact(&mut data)?;
+		decode(&data).map(Some).map_err(ChannelReaderError::InvalidBatch)
+	}
+}
+
+impl<R: Read> Iterator for ChannelReader<R> {
+	type Item = Result<Batch, ChannelReaderError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.done {
+			return None;
+		}
+		let res = self.next_batch().transpose();
+		self.done = !matches!(res, Some(Ok(_)));
+		res
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::batch::BatchV1;
+	use ethers_core::{types::H256, utils::rlp::encode};
+	use flate2::{write::ZlibEncoder, Compression};
+	use std::io::Write;
+
+	fn batch(timestamp: u64) -> Batch {
+		Batch {
+			batch: BatchV1 {
+				parent_hash: H256::zero(),
+				epoch_num: 1,
+				epoch_hash: H256::zero(),
+				timestamp,
+				transactions: vec![vec![0xaa; 10]],
+			},
+		}
+	}
+
+	// channel compresses the RLP encoding of each batch followed by the tail.
+	fn channel(batches: &[Batch], tail: &[u8]) -> Vec<u8> {
+		let mut compressor = ZlibEncoder::new(Vec::new(), Compression::default());
+		for b in batches {
+			compressor.write_all(&encode(&b.encode())).unwrap();
+		}
+		compressor.write_all(tail).unwrap();
+		compressor.finish().unwrap()
+	}
+
+	fn timestamps(reader: impl Iterator<Item = Result<Batch, ChannelReaderError>>) -> Vec<Result<u64, String>> {
+		reader.map(|b| b.map(|b| b.batch.timestamp).map_err(|err| err.to_string()))
+			.collect()
+	}
+
+	#[test]
+	fn test_channel_reader() {
+		let data = channel(&[batch(1), batch(2), batch(3)], &[]);
+		assert_eq!(timestamps(ChannelReader::new(&data[..])), [Ok(1), Ok(2), Ok(3)]);
+		assert!(ChannelReader::new(&channel(&[], &[])[..]).next().is_none());
+	}
+
+	#[test]
+	fn test_channel_reader_corrupt_tail() {
+		// The batches before the error are kept & nothing after it is read.
+		let data = channel(&[batch(1), batch(2)], &[0xc1, 0x80]);
+		let read = timestamps(ChannelReader::new(&data[..]));
+		assert_eq!(read[..2], [Ok(1), Ok(2)]);
+		assert_eq!(read.len(), 3);
+
+		let data = channel(&[batch(1)], &[0x83, 0x00, 0x01, 0x02]);
+		let mut reader = ChannelReader::new(&data[..]);
+		assert!(reader.next().unwrap().is_ok());
+		assert!(matches!(reader.next(), Some(Err(ChannelReaderError::InvalidBatch(_)))));
+		assert!(reader.next().is_none());
+
+		let data = channel(&[batch(1)], &[0x85, 0x00]);
+		let mut reader = ChannelReader::new(&data[..]);
+		assert!(reader.next().unwrap().is_ok());
+		assert!(matches!(reader.next(), Some(Err(ChannelReaderError::Truncated))));
+		assert!(reader.next().is_none());
+	}
+
+	#[test]
+	fn test_channel_reader_empty_batch() {
+		// An empty RLP string has no version byte.
+		let data = channel(&[batch(1)], &[0x80]);
+		let mut reader = ChannelReader::new(&data[..]);
+		assert!(reader.next().unwrap().is_ok());
+		assert!(matches!(
+			reader.next(),
+			Some(Err(ChannelReaderError::InvalidBatch(DecoderError::RlpIsTooShort)))
+		));
+		assert!(reader.next().is_none());
+	}
+
+	#[test]
+	fn test_channel_reader_not_zlib() {
+		let mut reader = ChannelReader::new(&[0xde, 0xad, 0xbe, 0xef][..]);
+		assert!(matches!(reader.next(), Some(Err(ChannelReaderError::Decompress(_)))));
+		assert!(reader.next().is_none());
+
+		// Valid zlib data that is cut short loses the batches that were not decompressed.
+		let data = channel(&[batch(1)], &[]);
+		assert!(timestamps(ChannelReader::new(&data[..data.len() / 2])).iter().all(|b| b.is_err()));
+	}
+
+	#[test]
+	fn test_channel_reader_limit() {
+		let data = channel(&[batch(1), batch(2)], &[]);
+		let len = encode(&batch(1).encode()).len() as u64;
+		assert_eq!(timestamps(ChannelReader::with_limit(&data[..], 2 * len)), [Ok(1), Ok(2)]);
+		assert_eq!(timestamps(ChannelReader::with_limit(&data[..], len)), [Ok(1)]);
+		let mut reader = ChannelReader::with_limit(&data[..], len + 3);
+		assert!(reader.next().unwrap().is_ok());
+		assert!(matches!(reader.next(), Some(Err(ChannelReaderError::TooLarge(_)))));
+
+		// A length that is past the limit is rejected before the data is read.
+		let data = channel(&[], &[0xbb, 0xff, 0xff, 0xff, 0xff]);
+		assert!(matches!(
+			ChannelReader::new(&data[..]).next(),
+			Some(Err(ChannelReaderError::TooLarge(0xffffffff)))
+		));
+	}
+}
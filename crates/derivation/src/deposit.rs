@@ -0,0 +1,302 @@
+use core::prelude::*;
+
+use ethers_core::{
+	types::{Log, H160, H256, U256},
+	utils::{
+		keccak256,
+		rlp::{encode, Encodable, RlpStream},
+	},
+};
+use hex_literal::hex;
+use std::fmt::Display;
+
+/// DEPOSIT_TX_TYPE is the EIP-2718 type of deposit transactions.
+pub const DEPOSIT_TX_TYPE: u8 = 0x7e;
+
+/// DEPOSIT_EVENT_TOPIC is the topic of `TransactionDeposited(address,address,uint256,bytes)`.
+const DEPOSIT_EVENT_TOPIC: [u8; 32] = hex!("b3813568d9991fc951961fcb4c784893574240a28925604d09fc577c55bb7c32");
+
+/// USER_DEPOSIT_SOURCE_DOMAIN & L1_INFO_DEPOSIT_SOURCE_DOMAIN keep the source hashes of the two
+/// kinds of deposits apart.
+pub const USER_DEPOSIT_SOURCE_DOMAIN: u64 = 0;
+pub const L1_INFO_DEPOSIT_SOURCE_DOMAIN: u64 = 1;
+
+/// DepositTx is a transaction that is derived from L1 instead of being signed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DepositTx {
+	pub source_hash: H256,
+	pub from: H160,
+	/// None for contract creations.
+	pub to: Option<H160>,
+	pub mint: U256,
+	pub value: U256,
+	pub gas: u64,
+	pub is_system_tx: bool,
+	pub data: Vec<u8>,
+}
+
+impl DepositTx {
+	/// encode returns the EIP-2718 encoding of the transaction.
+	pub fn encode(&self) -> Vec<u8> {
+		let mut tx = vec![DEPOSIT_TX_TYPE];
+		tx.extend_from_slice(&encode(self));
+		tx
+	}
+}
+
+impl Encodable for DepositTx {
+	fn rlp_append(&self, s: &mut RlpStream) {
+		s.begin_list(8);
+		s.append(&self.source_hash);
+		s.append(&self.from);
+		match &self.to {
+			Some(to) => s.append(to),
+			None => s.append_empty_data(),
+		};
+		s.append(&self.mint);
+		s.append(&self.value);
+		s.append(&self.gas);
+		s.append(&self.is_system_tx);
+		s.append(&self.data);
+	}
+}
+
+/// source_hash uniquely identifies a deposit by the L1 block & the index of the deposit in it.
+pub fn source_hash(domain: u64, l1_block_hash: Hash, index: u64) -> H256 {
+	let mut deposit_id = [0u8; 64];
+	deposit_id[..32].copy_from_slice(&l1_block_hash.to_vec());
+	deposit_id[56..].copy_from_slice(&index.to_be_bytes());
+	let mut input = [0u8; 64];
+	input[24..32].copy_from_slice(&domain.to_be_bytes());
+	input[32..].copy_from_slice(&keccak256(deposit_id));
+	H256(keccak256(input))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DepositError {
+	/// The log does not have the from, to & version topics.
+	MissingTopics(usize),
+	/// The version of the deposit event is not 0.
+	UnknownVersion(H256),
+	/// The event data is not an ABI encoded `bytes`.
+	InvalidData,
+	/// The opaque data is shorter than the mint, value, gas & is_creation fields.
+	TruncatedOpaqueData(usize),
+	/// The log does not have its index in the block, which the source hash is derived from.
+	MissingLogIndex,
+}
+
+impl Display for DepositError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			DepositError::MissingTopics(n) => write!(f, "deposit log has {n} topics instead of 4"),
+			DepositError::UnknownVersion(v) => write!(f, "unknown deposit version {v:?}"),
+			DepositError::InvalidData => write!(f, "deposit log data is not ABI encoded bytes"),
+			DepositError::TruncatedOpaqueData(len) => write!(f, "deposit opaque data is only {len} bytes"),
+			DepositError::MissingLogIndex => write!(f, "deposit log has no log index"),
+		}
+	}
+}
+
+impl std::error::Error for DepositError {}
+
+/// user_deposits returns the deposits that are emitted by the deposit contract in the receipts of
+/// an L1 block. A deposit that can not be decoded is an error, as skipping it would derive a
+/// different L2 chain.
+pub fn user_deposits(receipts: &[Receipt], deposit_contract: Address, l1_block_hash: Hash) -> Result<Vec<DepositTx>, DepositError> {
+	let mut deposits = Vec::new();
+	for receipt in receipts.iter().filter(|r| r.status == Some(1.into())) {
+		for log in &receipt.logs {
+			if Address::from(log.address) != deposit_contract || log.topics.first() != Some(&H256(DEPOSIT_EVENT_TOPIC)) {
+				continue;
+			}
+			// The source hash uses the index of the log in the block, not in the receipt.
+			let log_index = log.log_index.ok_or(DepositError::MissingLogIndex)?;
+			deposits.push(decode_deposit(
+				log,
+				source_hash(USER_DEPOSIT_SOURCE_DOMAIN, l1_block_hash, log_index.as_u64()),
+			)?);
+		}
+	}
+	Ok(deposits)
+}
+
+// decode_deposit decodes a version 0 deposit event. The opaque data is the mint, value, gas &
+// is_creation fields followed by the calldata.
+fn decode_deposit(log: &Log, source_hash: H256) -> Result<DepositTx, DepositError> {
+	let [_, from, to, version] = log.topics[..] else {
+		return Err(DepositError::MissingTopics(log.topics.len()));
+	};
+	if version != H256::zero() {
+		return Err(DepositError::UnknownVersion(version));
+	}
+
+	// The data is the offset & length of the opaque data, which is padded to 32 bytes.
+	let data = &log.data[..];
+	if data.len() < 64 || U256::from_big_endian(&data[..32]) != U256::from(32) {
+		return Err(DepositError::InvalidData);
+	}
+	let len = U256::from_big_endian(&data[32..64]);
+	if len > U256::from(data.len() - 64) || len.as_usize().div_ceil(32) * 32 != data.len() - 64 {
+		return Err(DepositError::InvalidData);
+	}
+	let opaque = &data[64..64 + len.as_usize()];
+	if opaque.len() < 73 {
+		return Err(DepositError::TruncatedOpaqueData(opaque.len()));
+	}
+	let is_creation = opaque[72] != 0;
+
+	Ok(DepositTx {
+		source_hash,
+		from: H160::from(from),
+		to: (!is_creation).then(|| H160::from(to)),
+		mint: U256::from_big_endian(&opaque[..32]),
+		value: U256::from_big_endian(&opaque[32..64]),
+		gas: u64::from_be_bytes(opaque[64..72].try_into().unwrap()),
+		is_system_tx: false,
+		data: opaque[73..].to_vec(),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use ethers_core::{
+		types::TransactionReceipt,
+		utils::rlp::{Decodable, Rlp},
+	};
+
+	const DEPOSIT_CONTRACT: [u8; 20] = [0xdd; 20];
+
+	// deposit_log encodes a deposit event with the opaque data of the deposit at log_index in the
+	// block.
+	fn deposit_log(deposit: &DepositTx, is_creation: u8, log_index: u64) -> Log {
+		let mut opaque = Vec::new();
+		opaque.extend_from_slice(&<[u8; 32]>::from(deposit.mint));
+		opaque.extend_from_slice(&<[u8; 32]>::from(deposit.value));
+		opaque.extend_from_slice(&deposit.gas.to_be_bytes());
+		opaque.push(is_creation);
+		opaque.extend_from_slice(&deposit.data);
+		let mut data = <[u8; 32]>::from(U256::from(32)).to_vec();
+		data.extend_from_slice(&<[u8; 32]>::from(U256::from(opaque.len())));
+		data.extend_from_slice(&opaque);
+		data.resize(64 + opaque.len().div_ceil(32) * 32, 0);
+		Log {
+			address: H160(DEPOSIT_CONTRACT),
+			topics: vec![
+				H256(DEPOSIT_EVENT_TOPIC),
+				H256::from(deposit.from),
+				H256::from(deposit.to.unwrap_or_default()),
+				H256::zero(),
+			],
+			data: data.into(),
+			log_index: Some(log_index.into()),
+			..Default::default()
+		}
+	}
+
+	fn receipt(logs: Vec<Log>) -> TransactionReceipt {
+		TransactionReceipt {
+			status: Some(1.into()),
+			logs,
+			..Default::default()
+		}
+	}
+
+	fn deposit(source_hash: H256, to: Option<H160>) -> DepositTx {
+		DepositTx {
+			source_hash,
+			from: H160::repeat_byte(1),
+			to,
+			mint: U256::from(1_000_000_000u64),
+			value: U256::from(7),
+			gas: 100_000,
+			is_system_tx: false,
+			data: vec![0xca, 0xfe],
+		}
+	}
+
+	#[test]
+	fn test_deposit_tx_encode() {
+		let tx = deposit(H256::repeat_byte(9), Some(H160::repeat_byte(2))).encode();
+		assert_eq!(tx[0], DEPOSIT_TX_TYPE);
+		let rlp = Rlp::new(&tx[1..]);
+		assert_eq!(rlp.item_count().unwrap(), 8);
+		assert_eq!(rlp.val_at::<H256>(0).unwrap(), H256::repeat_byte(9));
+		assert_eq!(rlp.val_at::<H160>(2).unwrap(), H160::repeat_byte(2));
+		assert_eq!(rlp.val_at::<U256>(3).unwrap(), U256::from(1_000_000_000u64));
+		assert_eq!(rlp.val_at::<u64>(5).unwrap(), 100_000);
+		assert!(!bool::decode(&rlp.at(6).unwrap()).unwrap());
+		assert_eq!(rlp.val_at::<Vec<u8>>(7).unwrap(), [0xca, 0xfe]);
+
+		// Contract creations have an empty to field.
+		let tx = deposit(H256::zero(), None).encode();
+		assert!(Rlp::new(&tx[1..]).at(2).unwrap().is_empty());
+	}
+
+	#[test]
+	fn test_user_deposits() {
+		let block_hash = Hash::new([3; 32]);
+		let call = deposit(source_hash(USER_DEPOSIT_SOURCE_DOMAIN, block_hash, 1), Some(H160::repeat_byte(2)));
+		let create = deposit(source_hash(USER_DEPOSIT_SOURCE_DOMAIN, block_hash, 3), None);
+		let other = |log_index| Log {
+			address: H160::repeat_byte(4),
+			..deposit_log(&call, 0, log_index)
+		};
+		let failed = TransactionReceipt {
+			status: Some(0.into()),
+			..receipt(vec![deposit_log(&call, 0, 4)])
+		};
+		// Any non-zero is_creation byte is a contract creation.
+		let receipts = [
+			receipt(vec![other(0), deposit_log(&call, 0, 1)]),
+			receipt(vec![other(2)]),
+			receipt(vec![deposit_log(&create, 2, 3)]),
+			failed,
+		];
+		let contract = Address::new(DEPOSIT_CONTRACT);
+		assert_eq!(user_deposits(&receipts, contract, block_hash), Ok(vec![call.clone(), create]));
+
+		// An invalid deposit fails the whole block instead of being skipped.
+		let mut invalid = deposit_log(&call, 0, 5);
+		invalid.topics.pop();
+		let receipts = [receipt(vec![deposit_log(&call, 0, 1)]), receipt(vec![invalid])];
+		assert_eq!(user_deposits(&receipts, contract, block_hash), Err(DepositError::MissingTopics(3)));
+		let missing_index = Log {
+			log_index: None,
+			..deposit_log(&call, 0, 1)
+		};
+		assert_eq!(
+			user_deposits(&[receipt(vec![missing_index])], contract, block_hash),
+			Err(DepositError::MissingLogIndex)
+		);
+	}
+
+	#[test]
+	fn test_decode_deposit_errors() {
+		let d = deposit(H256::zero(), Some(H160::repeat_byte(2)));
+		let log = deposit_log(&d, 0, 0);
+
+		let mut invalid = log.clone();
+		invalid.topics.pop();
+		assert_eq!(decode_deposit(&invalid, H256::zero()), Err(DepositError::MissingTopics(3)));
+		let mut invalid = log.clone();
+		invalid.topics[3] = H256::from_low_u64_be(1);
+		assert_eq!(
+			decode_deposit(&invalid, H256::zero()),
+			Err(DepositError::UnknownVersion(invalid.topics[3]))
+		);
+		let invalid = Log {
+			data: log.data[..log.data.len() - 1].to_vec().into(),
+			..log.clone()
+		};
+		assert_eq!(decode_deposit(&invalid, H256::zero()), Err(DepositError::InvalidData));
+		let short = Log {
+			data: [<[u8; 32]>::from(U256::from(32)), <[u8; 32]>::from(U256::from(32)), [0; 32]]
+				.concat()
+				.into(),
+			..log
+		};
+		assert_eq!(decode_deposit(&short, H256::zero()), Err(DepositError::TruncatedOpaqueData(32)));
+	}
+}
@@ -1,18 +1,22 @@
-use crate::batch::parse_batches;
+use crate::attributes::*;
 use crate::batch_queue::*;
 use crate::channel_bank::*;
+use crate::channel_reader::ChannelReader;
+use crate::deposit::DepositError;
 use crate::frame::parse_frames;
 use crate::read_adapter::ReadAdpater;
 
 use core::prelude::*;
 
-use flate2::read::ZlibDecoder;
-use std::io::Read;
+use ethers_core::types::H256;
 
 #[derive(Debug)]
 pub struct Derivation {
 	channel_bank: ChannelBank,
 	batch_queue: BatchQueue,
+	attributes_queue: AttributesQueue,
+	// The candidate that was taken from the batch queue before its L1 origin was loaded.
+	pending: Option<L2BlockCandidate>,
 	config: RollupConfig,
 }
 
@@ -21,54 +25,335 @@ impl Derivation {
 		Self {
 			channel_bank: ChannelBank::new(cfg),
 			batch_queue: BatchQueue::new(cfg),
+			attributes_queue: AttributesQueue::new(cfg),
+			pending: None,
 			config: cfg,
 		}
 	}
-	pub fn load_l1_data(&mut self, l1_block: L1BlockRef, transactions: Vec<Transaction>, _receipts: Vec<Receipt>) {
+	/// load_l1_data loads the batches & deposits of an L1 block. Nothing is loaded if the block
+	/// has an invalid deposit.
+	pub fn load_l1_data(
+		&mut self,
+		l1_info: L1BlockInfo,
+		transactions: Vec<Transaction>,
+		receipts: Vec<Receipt>,
+	) -> Result<(), DepositError> {
 		// TODO: update system config from receipts
+		self.attributes_queue.load_l1_block(l1_info, &receipts)?;
 
+		let l1_block = L1BlockRef::from(l1_info);
 		let sys_config = self.config.system_config;
 
 		let batches = transactions
 			.into_iter()
 			.filter(|tx| tx.to == Some(self.config.batch_inbox_address))
 			.filter(|tx| tx.from == sys_config.batcher_address)
-			.flat_map(|tx| parse_frames(&tx.input))
+			.flat_map(|tx| match parse_frames(&tx.input) {
+				Ok(frames) => frames,
+				Err(err) => {
+					log::warn!("dropping batcher transaction {:?}: {err}", H256::from(tx.hash));
+					Vec::new()
+				}
+			})
 			.reassemble_channels(&mut self.channel_bank, l1_block.into())
 			.map(|c| c.data())
 			.map(ReadAdpater::new)
-			.map(decompress)
-			.flat_map(parse_batches);
+			.flat_map(ChannelReader::new)
+			.filter_map(|batch| match batch {
+				Ok(batch) => Some(batch),
+				Err(err) => {
+					log::warn!("dropping the rest of the channel: {err}");
+					None
+				}
+			});
 		self.batch_queue.load_batches(batches, l1_block);
+		for (id, reason) in self.channel_bank.take_dropped() {
+			log::warn!("dropped channel {:?}: {reason:?}", id);
+		}
+		Ok(())
+	}
+
+	/// next_l2_attributes returns the payload attributes of the block after l2_head.
+	pub fn next_l2_attributes(&mut self, l2_head: L2BlockRef) -> Option<PayloadAttributes> {
+		self.next_l2_block(l2_head).map(|(_, attributes)| attributes)
 	}
 
-	pub fn next_l2_attributes(&mut self, l2_head: L2BlockRef) -> Option<L2BlockCandidate> {
-		self.batch_queue.get_block_candidate(l2_head)
+	// next_l2_block returns the next candidate & its payload attributes. The batch queue has moved
+	// past the candidate once it is returned, so a candidate without attributes is kept until
+	// they can be built. A kept candidate is dropped if the safe head is no longer its parent.
+	fn next_l2_block(&mut self, l2_head: L2BlockRef) -> Option<(L2BlockCandidate, PayloadAttributes)> {
+		let pending = self
+			.pending
+			.take()
+			.filter(|c| c.parent_hash == l2_head.hash && c.number == l2_head.number + 1);
+		let candidate = match pending {
+			Some(candidate) => candidate,
+			None => self.batch_queue.get_block_candidate(l2_head)?,
+		};
+		match self.attributes_queue.build_attributes(l2_head, &candidate) {
+			Some(attributes) => Some((candidate, attributes)),
+			None => {
+				self.pending = Some(candidate);
+				None
+			}
+		}
 	}
 
-	pub fn run(&mut self, start_l1_block: u64, end_l1_block: u64, l1_provider: &mut impl client::Provider) {
+	/// run derives the L2 blocks of the L1 blocks from start_l1_block up to end_l1_block. The safe
+	/// head starts at the L2 genesis & advances with every derived block. execute builds the block of
+	/// the attributes on top of the safe head & returns its hash, which the next batch must extend.
+	/// It stops at the first L1 block that can not be fetched or has an invalid deposit.
+	pub fn run(
+		&mut self,
+		start_l1_block: u64,
+		end_l1_block: u64,
+		l1_provider: &mut impl client::Provider,
+		mut execute: impl FnMut(L2BlockRef, &PayloadAttributes) -> eyre::Result<Hash>,
+	) -> eyre::Result<()> {
+		let mut l2_head = L2BlockRef {
+			hash: self.config.l2_genesis.hash,
+			number: self.config.l2_genesis.number,
+			time: self.config.l2_genesis_time,
+			l1_origin: self.config.l1_genesis,
+			..Default::default()
+		};
 		for i in start_l1_block..end_l1_block {
-			let header = l1_provider.get_header_by_number(i).unwrap();
-			let transactions = l1_provider.get_transactions_by_root(header.transactions_root.into()).unwrap();
-			self.load_l1_data(header.into(), transactions, Vec::default());
-			let mut l2_head = L2BlockRef {
-				time: self.config.l2_genesis_time,
-				..Default::default()
-			};
-			while let Some(candidate) = self.next_l2_attributes(l2_head) {
-				println!("{:?}", candidate);
-				l2_head.time = candidate.timestamp;
+			let header = l1_provider.get_header_by_number(i)?;
+			let transactions = l1_provider.get_transactions_by_root(header.transactions_root.into())?;
+			let receipts = l1_provider.get_receipts_by_root(header.receipts_root.into())?;
+			self.load_l1_data(header.into(), transactions, receipts)?;
+			while let Some((candidate, attributes)) = self.next_l2_block(l2_head) {
+				log::debug!("derived the attributes of L2 block {}: {:?}", candidate.number, attributes);
+				l2_head = L2BlockRef {
+					hash: execute(l2_head, &attributes)?,
+					number: candidate.number,
+					parent_hash: l2_head.hash,
+					time: attributes.timestamp,
+					l1_origin: candidate.l1_origin,
+					sequence_number: sequence_number(l2_head, candidate.l1_origin),
+				};
 			}
 		}
+		Ok(())
 	}
 }
 
-fn decompress(r: impl Read) -> Vec<u8> {
-	let mut decomp = ZlibDecoder::new(r);
-	let mut buffer = Vec::default();
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::batch::{Batch, BatchV1};
+	use crate::channel_out::ChannelOut;
+	use crate::deposit::DEPOSIT_TX_TYPE;
+	use crate::frame::frames_to_tx_data;
+	use core::chain_config::GOERLI_CONFIG;
+	use core::types::{ChannelID, Header};
+	use ethers_core::types::{Log, TransactionReceipt, H160};
+	use hex_literal::hex;
+	use std::collections::HashMap;
+
+	// Provider serves L1 blocks from memory. The transactions & receipts of a block are stored
+	// under its roots.
+	#[derive(Default)]
+	struct Provider {
+		headers: Vec<Header>,
+		transactions: HashMap<Hash, Vec<Transaction>>,
+		receipts: HashMap<Hash, Vec<Receipt>>,
+	}
+
+	impl Provider {
+		fn add_block(&mut self, header: Header, transactions: Vec<Transaction>, receipts: Vec<Receipt>) {
+			self.transactions.insert(header.transactions_root.into(), transactions);
+			self.receipts.insert(header.receipts_root.into(), receipts);
+			self.headers.push(header);
+		}
+	}
+
+	impl client::Provider for Provider {
+		fn get_header(&mut self, hash: Hash) -> eyre::Result<Header> {
+			let header = self.headers.iter().find(|h| Hash::from(h.hash_slow()) == hash);
+			header.cloned().ok_or(eyre::eyre!("unknown block"))
+		}
+		fn get_header_by_number(&mut self, n: u64) -> eyre::Result<Header> {
+			let header = self.headers.iter().find(|h| h.number == n);
+			header.cloned().ok_or(eyre::eyre!("unknown block"))
+		}
+		fn get_receipts_by_root(&self, root: Hash) -> eyre::Result<Vec<Receipt>> {
+			self.receipts.get(&root).cloned().ok_or(eyre::eyre!("unknown receipts"))
+		}
+		fn get_transactions_by_root(&self, root: Hash) -> eyre::Result<Vec<Transaction>> {
+			self.transactions.get(&root).cloned().ok_or(eyre::eyre!("unknown transactions"))
+		}
+	}
+
+	fn header(number: u64, time: u64) -> Header {
+		Header {
+			number,
+			timestamp: time,
+			transactions_root: [number as u8; 32].into(),
+			receipts_root: [number as u8 ^ 0x80; 32].into(),
+			..Default::default()
+		}
+	}
+
+	// batcher_tx puts the batches in a channel of their own & sends it in one transaction.
+	fn batcher_tx(cfg: &RollupConfig, batches: &[Batch]) -> Transaction {
+		let mut channel = ChannelOut::new(ChannelID::new([batches[0].batch.timestamp as u8; 16]));
+		for batch in batches {
+			channel.add_batch(batch).unwrap();
+		}
+		Transaction {
+			hash: Hash::default(),
+			to: Some(cfg.batch_inbox_address),
+			from: cfg.system_config.batcher_address,
+			input: frames_to_tx_data(&channel.close(1000).unwrap()),
+		}
+	}
+
+	// deposit_receipt has a deposit of 21000 gas from 0x11.. to 0x22.. at log_index 0.
+	fn deposit_receipt(cfg: &RollupConfig) -> Receipt {
+		let mut data = [[0u8; 32]; 5].concat();
+		data[31] = 32;
+		data[63] = 73;
+		data[64 + 64 + 5..64 + 72].copy_from_slice(&hex!("005208"));
+		TransactionReceipt {
+			status: Some(1.into()),
+			logs: vec![Log {
+				address: H160::from_slice(&cfg.deposit_contract_address.to_vec()),
+				topics: vec![
+					H256(hex!("b3813568d9991fc951961fcb4c784893574240a28925604d09fc577c55bb7c32")),
+					H256::repeat_byte(0x11),
+					H256::repeat_byte(0x22),
+					H256::zero(),
+				],
+				data: data.into(),
+				log_index: Some(0.into()),
+				..Default::default()
+			}],
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn test_run() {
+		let t = GOERLI_CONFIG.l2_genesis_time;
+		let h0 = header(GOERLI_CONFIG.l1_genesis.number, t);
+		let h1 = header(h0.number + 1, t + 4);
+		let cfg = RollupConfig {
+			l1_genesis: h0.clone().into(),
+			..GOERLI_CONFIG
+		};
+		// execute names each block after its number.
+		let block_hash = |number: u64| Hash::new([number as u8; 32]);
+		let batch = |parent: Hash, epoch: &Header, timestamp: u64| Batch {
+			batch: BatchV1 {
+				parent_hash: parent.into(),
+				epoch_num: epoch.number,
+				epoch_hash: Hash::from(epoch.hash_slow()).into(),
+				timestamp,
+				transactions: vec![vec![0x02, timestamp as u8]],
+			},
+		};
+		let l2 = cfg.l2_genesis.number;
+
+		// The blocks of the second L1 block extend the block of the first one & start its epoch.
+		let mut provider = Provider::default();
+		provider.add_block(
+			h0.clone(),
+			vec![batcher_tx(&cfg, &[batch(cfg.l2_genesis.hash, &h0, t + 2)])],
+			vec![],
+		);
+		let batches = [batch(block_hash(l2 + 1), &h1, t + 4), batch(block_hash(l2 + 2), &h1, t + 6)];
+		provider.add_block(h1.clone(), vec![batcher_tx(&cfg, &batches)], vec![deposit_receipt(&cfg)]);
 
-	// TODO: Handle this error
-	// Decompress the passed data with zlib
-	decomp.read_to_end(&mut buffer).unwrap();
-	buffer
+		let mut derived = Vec::new();
+		let mut derivation = Derivation::new(cfg);
+		derivation
+			.run(h0.number, h1.number + 1, &mut provider, |l2_head, attributes| {
+				derived.push(attributes.clone());
+				Ok(block_hash(l2_head.number + 1))
+			})
+			.unwrap();
+		let timestamps: Vec<_> = derived.iter().map(|a| a.timestamp).collect();
+		assert_eq!(timestamps, [t + 2, t + 4, t + 6]);
+		// Only the first block of the epoch has the deposit after the L1 attributes deposit.
+		let tx_types: Vec<Vec<_>> = derived.iter().map(|a| a.transactions.iter().map(|tx| tx[0]).collect()).collect();
+		assert_eq!(
+			tx_types,
+			[
+				vec![DEPOSIT_TX_TYPE, 0x02],
+				vec![DEPOSIT_TX_TYPE, DEPOSIT_TX_TYPE, 0x02],
+				vec![DEPOSIT_TX_TYPE, 0x02]
+			]
+		);
+
+		// An invalid deposit is returned as an error.
+		let mut invalid = deposit_receipt(&cfg);
+		invalid.logs[0].topics.pop();
+		let mut provider = Provider::default();
+		provider.add_block(h0.clone(), vec![], vec![invalid]);
+		let err = Derivation::new(cfg)
+			.run(h0.number, h0.number + 1, &mut provider, |_, _| unreachable!())
+			.unwrap_err();
+		assert_eq!(err.downcast_ref::<DepositError>(), Some(&DepositError::MissingTopics(3)));
+	}
+
+	// pending_candidate returns a derivation whose next candidate after the returned L2 genesis
+	// has no attributes, because its L1 origin is only loaded into the batch queue.
+	fn pending_candidate() -> (Derivation, L2BlockRef, L1BlockInfo) {
+		let cfg = GOERLI_CONFIG;
+		let mut derivation = Derivation::new(cfg);
+		let l1_info = L1BlockInfo {
+			number: cfg.l1_genesis.number,
+			hash: cfg.l1_genesis.hash,
+			time: cfg.l2_genesis_time,
+			..Default::default()
+		};
+		let batch = Batch {
+			batch: BatchV1 {
+				parent_hash: cfg.l2_genesis.hash.into(),
+				epoch_num: cfg.l1_genesis.number,
+				epoch_hash: cfg.l1_genesis.hash.into(),
+				timestamp: cfg.l2_genesis_time + 2,
+				transactions: vec![vec![0x02, 0xaa]],
+			},
+		};
+		derivation.batch_queue.load_batches(std::iter::once(batch), l1_info.into());
+		let l2_head = L2BlockRef {
+			hash: cfg.l2_genesis.hash,
+			number: cfg.l2_genesis.number,
+			time: cfg.l2_genesis_time,
+			l1_origin: cfg.l1_genesis,
+			..Default::default()
+		};
+		(derivation, l2_head, l1_info)
+	}
+
+	#[test]
+	fn test_next_l2_block_keeps_candidate() {
+		let (mut derivation, l2_head, l1_info) = pending_candidate();
+		assert!(derivation.next_l2_block(l2_head).is_none());
+		assert!(derivation.next_l2_block(l2_head).is_none());
+
+		derivation.attributes_queue.load_l1_block(l1_info, &[]).unwrap();
+		let (candidate, attributes) = derivation.next_l2_block(l2_head).unwrap();
+		assert_eq!(candidate.timestamp, GOERLI_CONFIG.l2_genesis_time + 2);
+		assert_eq!(attributes.transactions[1..], [vec![0x02, 0xaa]]);
+		assert!(derivation.next_l2_block(l2_head).is_none());
+	}
+
+	#[test]
+	fn test_next_l2_block_drops_stale_candidate() {
+		let (mut derivation, l2_head, l1_info) = pending_candidate();
+		assert!(derivation.next_l2_block(l2_head).is_none());
+		assert!(derivation.pending.is_some());
+
+		// The candidate does not build on a different safe head.
+		derivation.attributes_queue.load_l1_block(l1_info, &[]).unwrap();
+		let other = L2BlockRef {
+			hash: Hash::new([1; 32]),
+			..l2_head
+		};
+		assert!(derivation.next_l2_block(other).is_none());
+		assert!(derivation.pending.is_none());
+	}
 }
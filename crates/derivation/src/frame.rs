@@ -3,12 +3,28 @@ use nom::{
 	branch::alt,
 	bytes::complete::{tag, take},
 	combinator::{map, map_res},
-	multi::many0,
-	number::complete::{be_u16, be_u32},
+	number::complete::{be_u16, be_u32, be_u8},
+	sequence::tuple,
 	IResult,
 };
+use std::fmt::Display;
 
-#[derive(Debug)]
+/// DERIVATION_VERSION_0 is the version byte that prefixes the frames in a batcher transaction.
+pub const DERIVATION_VERSION_0: u8 = 0;
+
+/// MAX_DATA_LEN is the maximum size of the data of a single frame.
+pub const MAX_DATA_LEN: u32 = 1_000_000;
+
+/// CHANNEL_ID_LEN is the size of the channel id that starts every frame.
+const CHANNEL_ID_LEN: usize = 16;
+
+/// FRAME_HEADER_LEN is the size of the channel id, frame number & data length of a frame.
+const FRAME_HEADER_LEN: usize = CHANNEL_ID_LEN + 2 + 4;
+
+/// FRAME_OVERHEAD is the size of an encoded frame without its data.
+pub const FRAME_OVERHEAD: usize = FRAME_HEADER_LEN + 1;
+
+#[derive(Debug, PartialEq, Eq)]
 pub struct Frame {
 	pub id: ChannelID,
 	pub number: u16,
@@ -20,25 +36,94 @@ impl Frame {
 	pub fn size(&self) -> u64 {
 		self.data.len() as u64 + 200
 	}
+
+	/// encode appends the frame to buf in the format that parse_frames reads.
+	pub fn encode(&self, buf: &mut Vec<u8>) {
+		buf.extend_from_slice(&self.id.to_vec());
+		buf.extend_from_slice(&self.number.to_be_bytes());
+		buf.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+		buf.extend_from_slice(&self.data);
+		buf.push(self.is_last as u8);
+	}
 }
 
-pub fn parse_frames(tx_data: &[u8]) -> Vec<Frame> {
-	parse_frames_nom(tx_data).map(|(_, frames)| frames).unwrap_or_default()
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrameError {
+	/// The transaction does not start with a known derivation version.
+	BadVersion(u8),
+	/// The transaction has no frames after the version byte.
+	NoFrames,
+	/// The data ends after the channel id of a frame, but before the rest of its header.
+	TruncatedHeader,
+	/// The frame claims more data than MAX_DATA_LEN.
+	OversizedFrame(u32),
+	/// The data ends before the frame's data & is_last byte do.
+	TruncatedData,
+	/// The is_last byte is neither 0 nor 1.
+	InvalidIsLast(u8),
+	/// There are bytes after the last frame, or after the version byte, that are too short to
+	/// hold a channel id.
+	TrailingData(usize),
 }
 
-fn parse_frames_nom(i: &[u8]) -> IResult<&[u8], Vec<Frame>> {
-	let (i, _) = tag([0])(i)?;
-	let (i, frames) = many0(parse_frame)(i)?;
-	Ok((i, frames))
+impl Display for FrameError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			FrameError::BadVersion(v) => write!(f, "unknown derivation version {v}"),
+			FrameError::NoFrames => write!(f, "batcher transaction has no frames"),
+			FrameError::TruncatedHeader => write!(f, "truncated frame header"),
+			FrameError::OversizedFrame(len) => write!(f, "frame data length {len} exceeds {MAX_DATA_LEN}"),
+			FrameError::TruncatedData => write!(f, "truncated frame data"),
+			FrameError::InvalidIsLast(b) => write!(f, "invalid is_last byte {b}"),
+			FrameError::TrailingData(len) => write!(f, "{len} trailing bytes after the last frame"),
+		}
+	}
 }
 
-fn parse_frame(i: &[u8]) -> IResult<&[u8], Frame> {
-	let (i, id) = map_res(take(4usize), ChannelID::try_from)(i)?;
-	let (i, number) = be_u16(i)?;
-	let (i, data_len) = be_u32(i)?;
-	// TODO: Validate data_len against MAX_DATA_LEN
-	let (i, data) = take(data_len as usize)(i)?;
-	let (i, is_last) = parse_bool(i)?;
+impl std::error::Error for FrameError {}
+
+/// parse_frames parses the frames of a batcher transaction. A transaction with any malformed
+/// frame, or without any frames, is rejected as a whole, so none of its frames are returned.
+pub fn parse_frames(tx_data: &[u8]) -> Result<Vec<Frame>, FrameError> {
+	let (mut i, version) = be_u8::<_, ()>(tx_data).map_err(|_| FrameError::NoFrames)?;
+	if version != DERIVATION_VERSION_0 {
+		return Err(FrameError::BadVersion(version));
+	}
+	if i.is_empty() {
+		return Err(FrameError::NoFrames);
+	}
+	let mut frames = Vec::new();
+	while !i.is_empty() {
+		if i.len() < CHANNEL_ID_LEN {
+			return Err(FrameError::TrailingData(i.len()));
+		}
+		let (rest, frame) = parse_frame(i)?;
+		frames.push(frame);
+		i = rest;
+	}
+	Ok(frames)
+}
+
+/// frames_to_tx_data encodes the frames as the calldata of a batcher transaction.
+pub fn frames_to_tx_data(frames: &[Frame]) -> Vec<u8> {
+	let mut tx_data = vec![DERIVATION_VERSION_0];
+	for frame in frames {
+		frame.encode(&mut tx_data);
+	}
+	tx_data
+}
+
+fn parse_frame(i: &[u8]) -> Result<(&[u8], Frame), FrameError> {
+	let (i, (id, number, data_len)) = tuple((map_res(take(16usize), ChannelID::try_from), be_u16, be_u32))(i)
+		.map_err(|_: nom::Err<()>| FrameError::TruncatedHeader)?;
+	if data_len > MAX_DATA_LEN {
+		return Err(FrameError::OversizedFrame(data_len));
+	}
+	let (i, data) = take::<_, _, ()>(data_len as usize)(i).map_err(|_| FrameError::TruncatedData)?;
+	let (i, is_last) = parse_bool(i).map_err(|_| match i.first() {
+		Some(b) => FrameError::InvalidIsLast(*b),
+		None => FrameError::TruncatedData,
+	})?;
 	Ok((
 		i,
 		Frame {
@@ -73,4 +158,68 @@ mod tests {
 		assert!(parse_bool(&[]).is_err());
 		assert!(parse_bool(&[2]).is_err());
 	}
+
+	// frame_bytes encodes a frame of channel 0xaa.. with the given data length, data & is_last byte.
+	fn frame_bytes(number: u16, data_len: u32, data: &[u8], is_last: u8) -> Vec<u8> {
+		[&[0xaa; 16][..], &number.to_be_bytes(), &data_len.to_be_bytes(), data, &[is_last]].concat()
+	}
+
+	// tx_data prefixes the encoded frames with the derivation version.
+	fn tx_data(frames: &[&[u8]]) -> Vec<u8> {
+		[&[DERIVATION_VERSION_0][..], &frames.concat()].concat()
+	}
+
+	#[test]
+	fn test_parse_frames() {
+		let frames = parse_frames(&tx_data(&[&frame_bytes(0, 3, b"abc", 0), &frame_bytes(1, 0, b"", 1)])).unwrap();
+		assert_eq!(frames.len(), 2);
+		assert_eq!(frames[0].id, ChannelID::new([0xaa; 16]));
+		assert_eq!((frames[0].number, &frames[0].data[..], frames[0].is_last), (0, &b"abc"[..], false));
+		assert_eq!((frames[1].number, &frames[1].data[..], frames[1].is_last), (1, &b""[..], true));
+	}
+
+	#[test]
+	fn test_parse_frames_errors() {
+		let frame = frame_bytes(0, 3, b"abc", 1);
+		assert_eq!(parse_frames(&[]).unwrap_err(), FrameError::NoFrames);
+		assert_eq!(parse_frames(&tx_data(&[])).unwrap_err(), FrameError::NoFrames);
+		assert_eq!(parse_frames(&[&[1], &frame[..]].concat()).unwrap_err(), FrameError::BadVersion(1));
+		assert_eq!(
+			parse_frames(&tx_data(&[&frame[..FRAME_HEADER_LEN - 1]])).unwrap_err(),
+			FrameError::TruncatedHeader
+		);
+		assert_eq!(
+			parse_frames(&tx_data(&[&frame[..CHANNEL_ID_LEN - 1]])).unwrap_err(),
+			FrameError::TrailingData(CHANNEL_ID_LEN - 1)
+		);
+		let oversized = frame_bytes(0, MAX_DATA_LEN + 1, b"abc", 1);
+		assert_eq!(
+			parse_frames(&tx_data(&[&oversized])).unwrap_err(),
+			FrameError::OversizedFrame(MAX_DATA_LEN + 1)
+		);
+		assert_eq!(
+			parse_frames(&tx_data(&[&frame_bytes(0, 4, b"abc", 1)])).unwrap_err(),
+			FrameError::TruncatedData
+		);
+		assert_eq!(
+			parse_frames(&tx_data(&[&frame[..frame.len() - 1]])).unwrap_err(),
+			FrameError::TruncatedData
+		);
+		let invalid = frame_bytes(0, 3, b"abc", 2);
+		assert_eq!(parse_frames(&tx_data(&[&invalid])).unwrap_err(), FrameError::InvalidIsLast(2));
+
+		// A valid frame followed by a malformed one rejects the whole transaction.
+		assert_eq!(
+			parse_frames(&tx_data(&[&frame, &invalid])).unwrap_err(),
+			FrameError::InvalidIsLast(2)
+		);
+		assert_eq!(
+			parse_frames(&tx_data(&[&frame, &[0xaa; 5]])).unwrap_err(),
+			FrameError::TrailingData(5)
+		);
+		assert_eq!(
+			parse_frames(&tx_data(&[&frame, &frame[..CHANNEL_ID_LEN + 2]])).unwrap_err(),
+			FrameError::TruncatedHeader
+		);
+	}
 }
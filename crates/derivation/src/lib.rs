@@ -1,11 +1,15 @@
 #![feature(hash_drain_filter)]
 #![feature(let_chains)]
 
+pub mod batch;
+pub mod channel_out;
 pub mod derivation;
+pub mod frame;
 
-mod batch;
+mod attributes;
 mod batch_queue;
 mod channel;
 mod channel_bank;
-mod frame;
+mod channel_reader;
+mod deposit;
 mod read_adapter;
@@ -2,17 +2,19 @@
 
 use libfuzzer_sys::fuzz_target;
 use mpt::MPT;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 fuzz_target!(|input: Vec<(Vec<u8>, Vec<u8>)>| {
-	let mut map = HashMap::new();
+	let mut map = BTreeMap::new();
 	let mut mpt = MPT::default();
 	for (k, v) in input.iter() {
 		map.insert(k.clone(), v.clone());
-		mpt.insert(k.clone(), v.clone());
+		mpt.insert(k.clone(), v.clone()).unwrap();
 	}
-	for (k, v) in map {
-		let stored = mpt.get(k.clone()).unwrap();
+	for (k, v) in map.iter() {
+		let stored = mpt.get(k.clone()).unwrap().unwrap();
 		assert_eq!(stored, &v[..], "MPT value != input value");
 	}
+	let contents: Vec<_> = mpt.iter().map(|entry| entry.map(|(k, v)| (k, v.to_vec())).unwrap()).collect();
+	assert!(contents.into_iter().eq(map), "MPT contents != input contents");
 });
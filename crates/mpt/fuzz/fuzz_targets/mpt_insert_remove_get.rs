@@ -0,0 +1,42 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mpt::MPT;
+use std::collections::HashMap;
+
+// Each op is (kind, key, value): 0 inserts, 1 removes & 2 looks up the key.
+fuzz_target!(|ops: Vec<(u8, Vec<u8>, Vec<u8>)>| {
+	let mut map = HashMap::new();
+	let mut mpt = MPT::default();
+	let mut absent = Vec::new();
+	for (kind, k, v) in ops {
+		match kind % 3 {
+			// Empty values are not stored in an Ethereum trie.
+			0 if v.is_empty() => continue,
+			0 => {
+				map.insert(k.clone(), v.clone());
+				mpt.insert(k, v).unwrap();
+			}
+			1 => {
+				assert_eq!(mpt.remove(k.clone()).unwrap(), map.remove(&k), "removed value != input value");
+				absent.push(k);
+			}
+			_ => assert_eq!(mpt.get(k.clone()).unwrap(), map.get(&k).map(|v| &v[..]), "MPT value != input value"),
+		}
+	}
+	for (k, v) in map.iter() {
+		assert_eq!(mpt.get(k.clone()).unwrap(), Some(&v[..]), "MPT value != input value");
+		// Keys that share a path with a stored key must not be found.
+		absent.push([&k[..], &[0]].concat());
+		if let Some((_, prefix)) = k.split_last() {
+			absent.push(prefix.to_vec());
+		}
+	}
+	for k in absent {
+		assert_eq!(
+			mpt.get(k.clone()).unwrap(),
+			map.get(&k).map(|v| &v[..]),
+			"MPT found a key that is not in the input"
+		);
+	}
+});
@@ -0,0 +1,47 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mpt::{verify_multiproof, verify_proof, verify_range_proof, MPT};
+use std::collections::BTreeMap;
+
+// Input is the entries of the trie & extra keys to prove.
+type Input = (Vec<(Vec<u8>, Vec<u8>)>, Vec<Vec<u8>>);
+
+fuzz_target!(|input: Input| {
+	let (entries, mut queries) = input;
+	let mut map = BTreeMap::new();
+	let mut mpt = MPT::default();
+	for (k, v) in entries {
+		// Empty values are not stored in an Ethereum trie.
+		if !v.is_empty() {
+			map.insert(k.clone(), v.clone());
+			mpt.insert(k, v).unwrap();
+		}
+	}
+	let root = mpt.hash();
+
+	queries.extend(map.keys().cloned());
+	for k in queries.iter() {
+		let proof = mpt.prove(k.clone()).unwrap();
+		assert_eq!(verify_proof(root, k, &proof), Ok(map.get(k).cloned()), "proof does not verify");
+	}
+
+	let proof = mpt.prove_multi(&queries).unwrap();
+	let expected: Vec<_> = queries.iter().map(|k| map.get(k).cloned()).collect();
+	assert_eq!(
+		verify_multiproof(root, &queries, &proof),
+		Ok(expected),
+		"multiproof does not verify"
+	);
+
+	if let [a, b, ..] = &queries[..] {
+		let (start, end) = if a <= b { (a, b) } else { (b, a) };
+		let proof = mpt.prove_range(start, end).unwrap();
+		let expected: Vec<_> = map.range(start.clone()..end.clone()).map(|(k, v)| (k.clone(), v.clone())).collect();
+		assert_eq!(
+			verify_range_proof(root, start, end, &proof),
+			Ok(expected),
+			"range proof does not verify"
+		);
+	}
+});
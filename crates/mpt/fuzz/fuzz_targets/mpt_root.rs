@@ -0,0 +1,37 @@
+#![no_main]
+
+mod reference;
+
+use libfuzzer_sys::fuzz_target;
+use mpt::{StackTrie, MPT};
+use std::collections::BTreeMap;
+
+// Each op is (kind, key, value): 0 inserts, 1 removes & 2 compares the roots. The roots are also
+// compared at the end, along with the root of a stack trie built from the final contents. Hashing
+// in between checks that cached encodings are cleared on writes.
+fuzz_target!(|ops: Vec<(u8, Vec<u8>, Vec<u8>)>| {
+	let mut map = BTreeMap::new();
+	let mut mpt = MPT::default();
+	for (kind, k, v) in ops {
+		match kind % 3 {
+			// Empty values are not stored in an Ethereum trie.
+			0 if v.is_empty() => continue,
+			0 => {
+				map.insert(k.clone(), v.clone());
+				mpt.insert(k, v).unwrap();
+			}
+			1 => {
+				map.remove(&k);
+				mpt.remove(k).unwrap();
+			}
+			_ => assert_eq!(mpt.hash(), reference::root(&map), "MPT root != reference root"),
+		}
+	}
+	let root = reference::root(&map);
+	assert_eq!(mpt.hash(), root, "MPT root != reference root");
+	let mut stack = StackTrie::new();
+	for (k, v) in map {
+		stack.insert(k, v).unwrap();
+	}
+	assert_eq!(stack.hash(), root, "stack trie root != reference root");
+});
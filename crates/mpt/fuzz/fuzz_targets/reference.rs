@@ -0,0 +1,93 @@
+// A deliberately naive Merkle Patricia Trie that builds every node from the sorted list of keys.
+// It shares no code with the mpt crate so that the roots of the two can be compared.
+
+use core::types::{keccak, Hash};
+use std::collections::BTreeMap;
+
+pub fn root(map: &BTreeMap<Vec<u8>, Vec<u8>>) -> Hash {
+	let items: Vec<(Vec<u8>, &[u8])> = map.iter().map(|(k, v)| (to_nibbles(k), &v[..])).collect();
+	if items.is_empty() {
+		return keccak([0x80]);
+	}
+	keccak(node(&items, 0))
+}
+
+fn node(items: &[(Vec<u8>, &[u8])], depth: usize) -> Vec<u8> {
+	if let [(key, value)] = items {
+		return list(&[string(&compact(&key[depth..], false)), string(value)]);
+	}
+	let prefix = common_prefix(items, depth);
+	if prefix > 0 {
+		let path = compact(&items[0].0[depth..depth + prefix], true);
+		return list(&[string(&path), reference(node(items, depth + prefix))]);
+	}
+	let mut children = Vec::new();
+	for nibble in 0..16 {
+		let child: Vec<_> = items
+			.iter()
+			.filter(|(k, _)| k.len() > depth && k[depth] == nibble)
+			.cloned()
+			.collect();
+		children.push(if child.is_empty() {
+			string(&[])
+		} else {
+			reference(node(&child, depth + 1))
+		});
+	}
+	let value = items.iter().find(|(k, _)| k.len() == depth).map_or(&[][..], |(_, v)| v);
+	children.push(string(value));
+	list(&children)
+}
+
+fn common_prefix(items: &[(Vec<u8>, &[u8])], depth: usize) -> usize {
+	let first = &items[0].0[depth..];
+	items.iter()
+		.map(|(k, _)| k[depth..].iter().zip(first).take_while(|(a, b)| a == b).count())
+		.min()
+		.unwrap()
+}
+
+fn reference(node: Vec<u8>) -> Vec<u8> {
+	if node.len() < 32 {
+		node
+	} else {
+		string(&keccak(&node).to_vec())
+	}
+}
+
+fn to_nibbles(key: &[u8]) -> Vec<u8> {
+	key.iter().flat_map(|b| [b >> 4, b & 0x0f]).collect()
+}
+
+fn compact(nibbles: &[u8], extension: bool) -> Vec<u8> {
+	let flag = if extension { 0 } else { 2 };
+	let mut out = if nibbles.len() % 2 == 1 {
+		vec![(flag + 1) << 4 | nibbles[0]]
+	} else {
+		vec![flag << 4]
+	};
+	let rest = &nibbles[nibbles.len() % 2..];
+	out.extend(rest.chunks(2).map(|c| c[0] << 4 | c[1]));
+	out
+}
+
+fn string(bytes: &[u8]) -> Vec<u8> {
+	if bytes.len() == 1 && bytes[0] < 0x80 {
+		return bytes.to_vec();
+	}
+	[header(0x80, bytes.len()), bytes.to_vec()].concat()
+}
+
+fn list(items: &[Vec<u8>]) -> Vec<u8> {
+	let payload = items.concat();
+	[header(0xc0, payload.len()), payload].concat()
+}
+
+fn header(offset: u8, len: usize) -> Vec<u8> {
+	if len < 56 {
+		return vec![offset + len as u8];
+	}
+	let len = len.to_be_bytes();
+	let len = &len[len.iter().position(|b| *b != 0).unwrap()..];
+	[vec![offset + 55 + len.len() as u8], len.to_vec()].concat()
+}
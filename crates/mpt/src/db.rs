@@ -0,0 +1,103 @@
+use crate::misc::RLPEncodeableWrapper;
+use crate::{NodeSource, NodeStore};
+use core::types::{keccak, Hash};
+use std::{collections::HashMap, io};
+
+// NodeDb holds the encodings of the trie's nodes by hash. It keeps track of how many nodes
+// in the trie use each encoding so that nodes which are no longer part of the trie can be
+// removed. Nodes that are not in the db are loaded from the store or the source.
+#[derive(Default)]
+pub(crate) struct NodeDb {
+	pub(crate) nodes: HashMap<Hash, Vec<u8>>,
+	refs: HashMap<Hash, usize>,
+	source: Option<Box<dyn NodeSource>>,
+	store: Option<Box<dyn NodeStore>>,
+}
+
+impl NodeDb {
+	pub(crate) fn with_source(source: impl NodeSource + 'static) -> Self {
+		Self {
+			source: Some(Box::new(source)),
+			..Default::default()
+		}
+	}
+
+	pub(crate) fn with_store(store: impl NodeStore + 'static) -> Self {
+		Self {
+			store: Some(Box::new(store)),
+			..Default::default()
+		}
+	}
+
+	// persist writes the nodes in the db to the store. The store skips nodes it already has.
+	pub(crate) fn persist(&mut self) -> io::Result<()> {
+		let Some(store) = self.store.as_mut() else { return Ok(()) };
+		for (hash, node) in self.nodes.iter() {
+			store.put_node(*hash, node)?;
+		}
+		store.flush()
+	}
+
+	// encode hashes a freshly encoded node. Nodes that are referenced by hash are stored.
+	pub(crate) fn encode(&mut self, rlp: Vec<u8>) -> Encoded {
+		let stored = rlp.len() >= 32;
+		self.store(rlp, stored)
+	}
+
+	// encode_root stores the encoding of the root, which is looked up by hash even if it is
+	// shorter than 32 bytes. It is released like any other node once the root changes.
+	pub(crate) fn encode_root(&mut self, rlp: Vec<u8>) -> Encoded {
+		self.store(rlp, true)
+	}
+
+	fn store(&mut self, rlp: Vec<u8>, stored: bool) -> Encoded {
+		let hash = keccak(&rlp);
+		if stored {
+			*self.refs.entry(hash).or_default() += 1;
+			self.nodes.insert(hash, rlp.clone());
+		}
+		Encoded { rlp, hash }
+	}
+
+	// release is called when a node changes or is removed from the trie. The old encoding
+	// is removed once no other node in the trie uses it.
+	pub(crate) fn release(&mut self, encoded: Option<Encoded>) {
+		let Some(encoded) = encoded else { return };
+		let Some(refs) = self.refs.get_mut(&encoded.hash) else { return };
+		*refs -= 1;
+		if *refs == 0 {
+			self.refs.remove(&encoded.hash);
+			self.nodes.remove(&encoded.hash);
+		}
+	}
+}
+
+// Nodes written by the trie are looked up before falling back to the store & the source.
+impl NodeSource for NodeDb {
+	fn get_node(&self, hash: &Hash) -> Option<Vec<u8>> {
+		self.nodes
+			.get(hash)
+			.cloned()
+			.or_else(|| self.store.as_ref()?.get_node(hash))
+			.or_else(|| self.source.as_ref()?.get_node(hash))
+	}
+}
+
+// Encoded is the cached encoding of a node. It is cleared whenever the node changes so
+// only the nodes on modified paths are encoded & hashed again.
+pub(crate) struct Encoded {
+	pub(crate) rlp: Vec<u8>,
+	pub(crate) hash: Hash,
+}
+
+impl Encoded {
+	// reference is how a parent refers to the node: by hash, or by the node's encoding if
+	// it is shorter than 32 bytes.
+	pub(crate) fn reference(&self) -> RLPEncodeableWrapper {
+		if self.rlp.len() < 32 {
+			RLPEncodeableWrapper::Raw(self.rlp.clone())
+		} else {
+			RLPEncodeableWrapper::Bytes(self.hash.to_vec())
+		}
+	}
+}
@@ -0,0 +1,169 @@
+use crate::misc::compact_to_nibbles;
+use crate::{BranchNode, ExtensionNode, HashNode, MptError, Node, NodeSource, ValueNode};
+use core::types::Hash;
+use reth_rlp::{DecodeError, Header};
+
+impl Node {
+	// decode builds a node from its RLP encoding. Children that are referenced by hash are
+	// left as hash nodes & inline children are decoded recursively.
+	pub(crate) fn decode(buf: &[u8]) -> Result<Node, DecodeError> {
+		match decode_node(buf)? {
+			RawNode::Empty => Ok(Node::Empty),
+			RawNode::Branch { children, value } => {
+				let mut node = BranchNode::default();
+				for (i, child) in children.into_iter().enumerate() {
+					*node.children[i] = child.into_node()?;
+				}
+				node.branch_value = value.map(|v| ValueNode::new(v.to_vec()));
+				Ok(node.into())
+			}
+			RawNode::Extension { nibbles, child } => match child.into_node()? {
+				child @ (Node::Branch(..) | Node::Hash(..)) => Ok(ExtensionNode::new_node(nibbles, Box::new(child))),
+				_ => Err(DecodeError::Custom("extension node must point to a branch node")),
+			},
+			RawNode::Leaf { nibbles, value } => Ok(Node::new(&nibbles, Node::new_value(value.to_vec()))),
+		}
+	}
+
+	// load_all replaces every hash node in the subtree with the decoded node from the source.
+	pub(crate) fn load_all(self, source: &dyn NodeSource) -> Result<Node, MptError> {
+		match self {
+			Node::Hash(node) => HashNode::load(node.hash, source)?.load_all(source),
+			Node::Branch(mut node) => {
+				for child in node.children.iter_mut() {
+					**child = std::mem::take(&mut **child).load_all(source)?;
+				}
+				Ok(node.into())
+			}
+			Node::Extension(node) => {
+				// A leaf embeds its value, so a value loaded by hash can't be the child of an extension.
+				let by_hash = matches!(*node.child, Node::Hash(..));
+				match node.child.load_all(source)? {
+					child @ Node::Branch(..) => Ok(ExtensionNode::new_node(node.nibbles, Box::new(child))),
+					child @ Node::Value(..) if !by_hash => Ok(ExtensionNode::new_node(node.nibbles, Box::new(child))),
+					_ => Err(MptError::InvalidExtension),
+				}
+			}
+			node => Ok(node),
+		}
+	}
+}
+
+// RawNode is a trie node decoded from its RLP encoding. Children are left as references
+// so that decoding a node never requires access to the rest of the trie.
+#[derive(Debug)]
+pub(crate) enum RawNode<'a> {
+	Empty,
+	Branch {
+		children: Vec<NodeRef<'a>>,
+		value: Option<&'a [u8]>,
+	},
+	Extension {
+		nibbles: Vec<u8>,
+		child: NodeRef<'a>,
+	},
+	Leaf {
+		nibbles: Vec<u8>,
+		value: &'a [u8],
+	},
+}
+
+// NodeRef is how a node refers to a child: by hash or by embedding the child's encoding.
+#[derive(Debug)]
+pub(crate) enum NodeRef<'a> {
+	Empty,
+	Hash(Hash),
+	Inline(&'a [u8]),
+}
+
+impl NodeRef<'_> {
+	fn into_node(self) -> Result<Node, DecodeError> {
+		match self {
+			NodeRef::Empty => Ok(Node::Empty),
+			NodeRef::Hash(hash) => Ok(Node::Hash(HashNode::new(hash))),
+			NodeRef::Inline(buf) => Node::decode(buf),
+		}
+	}
+}
+
+// decode_node decodes a single RLP encoded trie node.
+pub(crate) fn decode_node(buf: &[u8]) -> Result<RawNode<'_>, DecodeError> {
+	if buf == [0x80] {
+		return Ok(RawNode::Empty);
+	}
+	let items = decode_list(buf)?;
+	match items.len() {
+		17 => {
+			let children = items[..16].iter().map(|c| decode_ref(c)).collect::<Result<_, _>>()?;
+			let value = decode_bytes(items[16])?;
+			let value = if value.is_empty() { None } else { Some(value) };
+			Ok(RawNode::Branch { children, value })
+		}
+		2 => {
+			let path = decode_bytes(items[0])?;
+			let (nibbles, extension) = compact_to_nibbles(path).map_err(|_| DecodeError::Custom("invalid compact path"))?;
+			if extension {
+				if nibbles.is_empty() {
+					return Err(DecodeError::Custom("extension node without a path"));
+				}
+				match decode_ref(items[1])? {
+					NodeRef::Empty => Err(DecodeError::Custom("extension node without a child")),
+					child => Ok(RawNode::Extension { nibbles, child }),
+				}
+			} else {
+				Ok(RawNode::Leaf {
+					nibbles,
+					value: decode_bytes(items[1])?,
+				})
+			}
+		}
+		_ => Err(DecodeError::Custom("invalid number of items in trie node")),
+	}
+}
+
+// decode_ref decodes a child reference, which is either empty, a hash, or an inline node.
+fn decode_ref(item: &[u8]) -> Result<NodeRef<'_>, DecodeError> {
+	if item[0] >= 0xc0 {
+		if item.len() >= 32 {
+			return Err(DecodeError::Custom("inline node must be shorter than 32 bytes"));
+		}
+		return Ok(NodeRef::Inline(item));
+	}
+	let bytes = decode_bytes(item)?;
+	match bytes.len() {
+		0 => Ok(NodeRef::Empty),
+		32 => Ok(NodeRef::Hash(Hash::new(bytes.try_into().unwrap()))),
+		_ => Err(DecodeError::Custom("invalid child reference")),
+	}
+}
+
+// decode_list splits an RLP list into the raw encoding of each of its items.
+pub(crate) fn decode_list(buf: &[u8]) -> Result<Vec<&[u8]>, DecodeError> {
+	let mut payload = buf;
+	let header = Header::decode(&mut payload)?;
+	if !header.list {
+		return Err(DecodeError::UnexpectedString);
+	}
+	if payload.len() != header.payload_length {
+		return Err(DecodeError::Custom("trailing bytes after trie node"));
+	}
+	let mut items = Vec::new();
+	while !payload.is_empty() {
+		let item = payload;
+		let header = Header::decode(&mut payload)?;
+		// The header is not checked against the bytes that are left.
+		payload = payload.get(header.payload_length..).ok_or(DecodeError::InputTooShort)?;
+		items.push(&item[..item.len() - payload.len()]);
+	}
+	Ok(items)
+}
+
+// decode_bytes returns the payload of an RLP string.
+pub(crate) fn decode_bytes(item: &[u8]) -> Result<&[u8], DecodeError> {
+	let mut payload = item;
+	let header = Header::decode(&mut payload)?;
+	if header.list {
+		return Err(DecodeError::UnexpectedList);
+	}
+	payload.get(..header.payload_length).ok_or(DecodeError::InputTooShort)
+}
@@ -0,0 +1,103 @@
+use crate::misc::nibbles_to_bytes;
+use crate::{ExtensionNode, MptError, Node, NodeSource, MPT};
+use core::types::Hash;
+
+// Change is a difference in the value stored at a key between two tries.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change {
+	/// The key is only in the new trie.
+	Added { key: Vec<u8>, value: Vec<u8> },
+	/// The key is only in the old trie.
+	Removed { key: Vec<u8>, value: Vec<u8> },
+	/// The key is in both tries with different values.
+	Changed { key: Vec<u8>, old: Vec<u8>, new: Vec<u8> },
+}
+
+// diff_roots returns every key that differs between the tries at the old & new roots, in key
+// order. Both tries are read from the source & subtrees with the same hash are skipped.
+pub fn diff_roots(old: Hash, new: Hash, source: &dyn NodeSource) -> Result<Vec<Change>, MptError> {
+	let mut changes = Vec::new();
+	diff_nodes(
+		Node::new_hash(old),
+		source,
+		Node::new_hash(new),
+		source,
+		&mut Vec::new(),
+		&mut changes,
+	)?;
+	Ok(changes)
+}
+
+impl MPT {
+	// diff returns every key that differs between this trie & the other trie, in key order.
+	// Changes are from this trie to the other, so keys only in the other trie are added.
+	pub fn diff(&mut self, other: &mut MPT) -> Result<Vec<Change>, MptError> {
+		let (old, new) = (self.hash(), other.hash());
+		let mut changes = Vec::new();
+		diff_nodes(
+			Node::new_hash(old),
+			&self.db,
+			Node::new_hash(new),
+			&other.db,
+			&mut Vec::new(),
+			&mut changes,
+		)?;
+		Ok(changes)
+	}
+}
+
+// diff_nodes compares the subtrees at the same path in both tries. Nodes are expanded one
+// nibble at a time so that extensions & leaves line up with branches in the other trie.
+fn diff_nodes(
+	old: Node,
+	old_source: &dyn NodeSource,
+	new: Node,
+	new_source: &dyn NodeSource,
+	path: &mut Vec<u8>,
+	changes: &mut Vec<Change>,
+) -> Result<(), MptError> {
+	match (&old, &new) {
+		(Node::Empty, Node::Empty) => return Ok(()),
+		(Node::Hash(a), Node::Hash(b)) if a.hash == b.hash => return Ok(()),
+		_ => (),
+	}
+	let (old_value, old_children) = expand(old, old_source)?;
+	let (new_value, new_children) = expand(new, new_source)?;
+	let key = || nibbles_to_bytes(path);
+	match (old_value, new_value) {
+		(None, Some(value)) => changes.push(Change::Added { key: key(), value }),
+		(Some(value), None) => changes.push(Change::Removed { key: key(), value }),
+		(Some(old), Some(new)) if old != new => changes.push(Change::Changed { key: key(), old, new }),
+		_ => (),
+	}
+	for (i, (old, new)) in old_children.into_iter().zip(new_children).enumerate() {
+		path.push(i as u8);
+		diff_nodes(old, old_source, new, new_source, path, changes)?;
+		path.pop();
+	}
+	Ok(())
+}
+
+// expand splits a node into the value at its path & its 16 children.
+fn expand(node: Node, source: &dyn NodeSource) -> Result<(Option<Vec<u8>>, Vec<Node>), MptError> {
+	let mut children: Vec<Node> = (0..16).map(|_| Node::Empty).collect();
+	let value = match node {
+		Node::Empty => None,
+		Node::Value(node) => Some(node.value),
+		Node::Branch(node) => {
+			for (i, child) in node.children.into_iter().enumerate() {
+				children[i] = *child;
+			}
+			node.branch_value.map(|v| v.value)
+		}
+		Node::Extension(node) => {
+			children[node.nibbles[0] as usize] = match node.nibbles.len() {
+				1 => *node.child,
+				_ => ExtensionNode::new_node(node.nibbles[1..].to_vec(), node.child),
+			};
+			None
+		}
+		Node::Hash(node) => return expand(node.into_node(source)?, source),
+	};
+	Ok((value, children))
+}
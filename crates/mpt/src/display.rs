@@ -1,10 +1,10 @@
-use crate::{BranchNode, ExtensionNode, ValueNode, MPT};
+use crate::{BranchNode, ExtensionNode, HashNode, ValueNode, MPT};
 use std::fmt::Debug;
 
 impl Debug for MPT {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		f.write_fmt(format_args!("root: {:#?}\n", &self.root))?;
-		for (k, v) in self.db.iter() {
+		for (k, v) in self.db.nodes.iter() {
 			f.write_fmt(format_args!("{k:?}\t0x{}\n", hex::encode(v)))?;
 		}
 		Ok(())
@@ -37,3 +37,12 @@ impl Debug for ValueNode {
 		Ok(())
 	}
 }
+
+impl Debug for HashNode {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self.resolved.get() {
+			Some(node) => node.fmt(f),
+			None => f.write_fmt(format_args!("hash: {:?}", self.hash)),
+		}
+	}
+}
@@ -0,0 +1,43 @@
+use core::types::Hash;
+use reth_rlp::DecodeError;
+use std::fmt::Display;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MptError {
+	/// A node referenced by hash is not in the trie's db or source.
+	MissingNode(Hash),
+	/// A node returned by the source does not hash to the hash it was requested by.
+	HashMismatch(Hash),
+	/// A node is not a valid RLP encoded trie node.
+	InvalidNode(DecodeError),
+	/// A compact encoded path has an unknown prefix.
+	InvalidPath,
+	/// An extension node points to a node that is not a branch.
+	InvalidExtension,
+	/// A key was not inserted into a stack trie in increasing order.
+	UnsortedKey,
+	/// The checkpoint was already reverted past or committed.
+	InvalidCheckpoint,
+}
+
+impl Display for MptError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			MptError::MissingNode(hash) => write!(f, "missing trie node {hash:?}"),
+			MptError::HashMismatch(hash) => write!(f, "trie node does not match its hash {hash:?}"),
+			MptError::InvalidNode(err) => write!(f, "invalid trie node: {err}"),
+			MptError::InvalidPath => write!(f, "invalid compact path"),
+			MptError::InvalidExtension => write!(f, "extension node must point to a branch node"),
+			MptError::UnsortedKey => write!(f, "keys must be inserted in increasing order"),
+			MptError::InvalidCheckpoint => write!(f, "checkpoint was reverted or committed"),
+		}
+	}
+}
+
+impl std::error::Error for MptError {}
+
+impl From<DecodeError> for MptError {
+	fn from(err: DecodeError) -> Self {
+		MptError::InvalidNode(err)
+	}
+}
@@ -0,0 +1,102 @@
+use crate::db::NodeDb;
+use crate::misc::*;
+use crate::{MptError, Node, MPT};
+use std::ops::{Bound, RangeBounds};
+
+impl MPT {
+	// iter returns every key & value in the trie in lexicographic key order.
+	pub fn iter(&self) -> Iter<'_> {
+		Iter::new(&self.root, &self.db, Vec::new())
+	}
+
+	// range returns the keys & values in the trie that fall in the range, in lexicographic key order.
+	// Subtrees that sort before the start of the range are never visited.
+	pub fn range<K: AsRef<[u8]>>(&self, range: impl RangeBounds<K>) -> impl Iterator<Item = Result<(Vec<u8>, &[u8]), MptError>> {
+		let (start, excluded) = match range.start_bound() {
+			Bound::Included(k) => (Some(k.as_ref().to_vec()), false),
+			Bound::Excluded(k) => (Some(k.as_ref().to_vec()), true),
+			Bound::Unbounded => (None, false),
+		};
+		let end = match range.end_bound() {
+			Bound::Included(k) => Bound::Included(k.as_ref().to_vec()),
+			Bound::Excluded(k) => Bound::Excluded(k.as_ref().to_vec()),
+			Bound::Unbounded => Bound::Unbounded,
+		};
+		let iter = Iter::new(&self.root, &self.db, start.as_deref().map(bytes_to_nibbles).unwrap_or_default());
+		// Errors are passed through, the iterator stops after the first one.
+		iter.skip_while(move |entry| matches!(entry, Ok((k, _)) if excluded && Some(k) == start.as_ref()))
+			.take_while(move |entry| match (entry, &end) {
+				(Err(_), _) => true,
+				(Ok((k, _)), Bound::Included(end)) => k <= end,
+				(Ok((k, _)), Bound::Excluded(end)) => k < end,
+				(Ok(_), Bound::Unbounded) => true,
+			})
+	}
+}
+
+// Iter walks the trie depth first. Children of a branch are visited in nibble order after the
+// branch's own value, which is lexicographic order of the keys. If a node cannot be loaded the
+// error is returned & iteration ends.
+pub struct Iter<'a> {
+	stack: Vec<(Vec<u8>, &'a Node)>,
+	db: &'a NodeDb,
+	// start is the nibble path of the first key to return. Earlier keys are skipped.
+	start: Vec<u8>,
+}
+
+impl<'a> Iter<'a> {
+	fn new(root: &'a Node, db: &'a NodeDb, start: Vec<u8>) -> Self {
+		Self {
+			stack: vec![(Vec::new(), root)],
+			db,
+			start,
+		}
+	}
+
+	// before_start returns true if every key under path sorts before the start.
+	fn before_start(&self, path: &[u8]) -> bool {
+		let n = path.len().min(self.start.len());
+		path[..n] < self.start[..n]
+	}
+}
+
+impl<'a> Iterator for Iter<'a> {
+	type Item = Result<(Vec<u8>, &'a [u8]), MptError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		while let Some((path, node)) = self.stack.pop() {
+			if self.before_start(&path) {
+				continue;
+			}
+			match node {
+				Node::Empty => {}
+				Node::Branch(node) => {
+					for (i, child) in node.children.iter().enumerate().rev() {
+						let mut child_path = path.clone();
+						child_path.push(i as u8);
+						self.stack.push((child_path, child));
+					}
+					if let Some(value) = &node.branch_value {
+						if path >= self.start {
+							return Some(Ok((nibbles_to_bytes(&path), &value.value)));
+						}
+					}
+				}
+				Node::Extension(node) => self.stack.push(([&path[..], &node.nibbles[..]].concat(), &node.child)),
+				Node::Value(node) => {
+					if path >= self.start {
+						return Some(Ok((nibbles_to_bytes(&path), &node.value)));
+					}
+				}
+				Node::Hash(node) => match node.resolve(self.db) {
+					Ok(node) => self.stack.push((path, node)),
+					Err(err) => {
+						self.stack.clear();
+						return Some(Err(err));
+					}
+				},
+			}
+		}
+		None
+	}
+}
@@ -0,0 +1,84 @@
+use crate::{MptError, MPT};
+
+// Journal records the previous value of every key written while a checkpoint is open. Reverting
+// replays the journal backwards, so unchanged subtrees are never copied.
+#[derive(Debug, Default)]
+pub(crate) struct Journal {
+	entries: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+	active: bool,
+	// checkpoints are the checkpoints that can still be reverted to, oldest first.
+	checkpoints: Vec<Checkpoint>,
+	next_id: usize,
+}
+
+impl Journal {
+	pub(crate) fn is_active(&self) -> bool {
+		self.active
+	}
+
+	pub(crate) fn record(&mut self, k: Vec<u8>, previous: Option<Vec<u8>>) {
+		self.entries.push((k, previous));
+	}
+}
+
+// Checkpoint is a point in a trie's history that it can be reverted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+	id: usize,
+	index: usize,
+}
+
+impl MPT {
+	// checkpoint marks the current contents of the trie. Changes made after it can be undone
+	// with revert_to until commit is called. Checkpoints can be nested.
+	pub fn checkpoint(&mut self) -> Checkpoint {
+		self.journal.active = true;
+		let checkpoint = Checkpoint {
+			id: self.journal.next_id,
+			index: self.journal.entries.len(),
+		};
+		self.journal.next_id += 1;
+		self.journal.checkpoints.push(checkpoint);
+		checkpoint
+	}
+
+	// revert_to undoes every change made since the checkpoint. Checkpoints taken after it are
+	// no longer valid, but earlier ones are. Reverting to an invalid checkpoint is an error & leaves
+	// the trie unchanged.
+	// Undoing an insert may need nodes the insert never loaded, e.g. to collapse a branch the
+	// insert created above an unresolved hash node. If such a node is missing, the changes that
+	// could not be undone are kept in the journal so that revert_to can be called again.
+	pub fn revert_to(&mut self, checkpoint: Checkpoint) -> Result<(), MptError> {
+		let Some(i) = self.journal.checkpoints.iter().position(|c| *c == checkpoint) else {
+			return Err(MptError::InvalidCheckpoint);
+		};
+		self.journal.checkpoints.truncate(i + 1);
+		let active = std::mem::take(&mut self.journal.active);
+		let res = self.undo(checkpoint.index);
+		self.journal.active = active;
+		res
+	}
+
+	// undo replays the journal backwards down to index. An entry is only removed once it has
+	// been undone.
+	fn undo(&mut self, index: usize) -> Result<(), MptError> {
+		while self.journal.entries.len() > index {
+			let (k, previous) = self.journal.entries.last().cloned().unwrap();
+			match previous {
+				Some(v) => self.insert(k, v)?,
+				None => {
+					self.remove(k)?;
+				}
+			}
+			self.journal.entries.pop();
+		}
+		Ok(())
+	}
+
+	// commit keeps every change & discards all checkpoints.
+	pub fn commit(&mut self) {
+		self.journal.entries.clear();
+		self.journal.active = false;
+		self.journal.checkpoints.clear();
+	}
+}
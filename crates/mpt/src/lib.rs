@@ -1,31 +1,153 @@
+use crate::db::{Encoded, NodeDb};
+use crate::journal::Journal;
 use crate::misc::*;
-use core::types::{keccak, Hash};
-use std::{collections::HashMap, fmt::Debug};
+use core::{
+	hash_literal,
+	types::{keccak, Hash},
+};
+use std::{cell::OnceCell, collections::HashMap, fmt::Debug, io};
 
+mod db;
+mod decode;
+mod diff;
 mod display;
+mod error;
+mod iter;
+mod journal;
 mod misc;
+mod ordered;
+mod proof;
+mod secure;
+mod source;
+mod stack;
+mod state;
+mod store;
 #[cfg(test)]
 mod test;
 
+pub use diff::{diff_roots, Change};
+pub use error::MptError;
+pub use iter::Iter;
+pub use journal::Checkpoint;
+pub use ordered::{ordered_trie_hash, ordered_trie_key, ordered_trie_root};
+pub use proof::{verify_multiproof, verify_proof, verify_range_proof, ProofError};
+pub use secure::SecureMPT;
+pub use source::NodeSource;
+pub use stack::StackTrie;
+pub use state::{Account, StateDb, EMPTY_CODE_HASH};
+pub use store::{FileStore, NodeStore};
+
+// The compact encoding is exposed for the fuzz targets.
+#[doc(hidden)]
+pub use misc::{compact_to_nibbles, nibbles_to_compact};
+
+// EMPTY_ROOT is the root hash of a trie with no keys.
+pub const EMPTY_ROOT: Hash = hash_literal!("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421");
+
 #[derive(Default)]
 pub struct MPT {
 	root: Node,
-	db: HashMap<Hash, Vec<u8>>,
+	// root_encoded holds the db's reference to the root's encoding from the last call to hash.
+	root_encoded: Option<Encoded>,
+	db: NodeDb,
+	journal: Journal,
 }
 
 impl MPT {
+	// with_source creates a trie that only knows its root hash. Nodes are loaded from the
+	// source as they are needed, so reads & writes only touch the path to the key.
+	pub fn with_source(root: Hash, source: impl NodeSource + 'static) -> Self {
+		Self {
+			root: Node::new_hash(root),
+			root_encoded: None,
+			db: NodeDb::with_source(source),
+			journal: Journal::default(),
+		}
+	}
+
+	// with_store opens the trie at root from the store. Nodes are loaded from the store as they
+	// are needed & new nodes are written back to it by persist.
+	pub fn with_store(root: Hash, store: impl NodeStore + 'static) -> Self {
+		Self {
+			root: Node::new_hash(root),
+			root_encoded: None,
+			db: NodeDb::with_store(store),
+			journal: Journal::default(),
+		}
+	}
+
+	// persist writes every node of the trie that is not yet in the store to the store & returns
+	// the root hash the trie can be reopened from. Tries without a store are kept in memory only.
+	pub fn persist(&mut self) -> io::Result<Hash> {
+		let hash = self.hash();
+		self.db.persist()?;
+		Ok(hash)
+	}
+
+	// from_db decodes the full trie under root from the RLP encoded nodes in db. The trie is
+	// encoded again so that its db only holds the nodes of the trie, each counted as in use.
+	pub fn from_db(root: Hash, db: HashMap<Hash, Vec<u8>>) -> Result<Self, MptError> {
+		let root = Node::new_hash(root).load_all(&db)?;
+		let mut mpt = Self {
+			root,
+			..Default::default()
+		};
+		mpt.hash();
+		Ok(mpt)
+	}
+
+	// hash returns the root hash of the trie. Only nodes that changed since the last call
+	// are encoded & hashed again.
 	pub fn hash(&mut self) -> Hash {
-		keccak(self.root.rlp_bytes(&mut self.db))
+		let hash = self.root.hash(&mut self.db);
+		if self.root_encoded.as_ref().map(|encoded| encoded.hash) != Some(hash) {
+			let previous = self.root_encoded.take();
+			// The root is always stored by hash, even if it is shorter than 32 bytes.
+			if !matches!(self.root, Node::Hash(..)) {
+				let rlp = self.root.rlp_bytes(&mut self.db);
+				self.root_encoded = Some(self.db.encode_root(rlp));
+			}
+			self.db.release(previous);
+		}
+		hash
 	}
 
-	pub fn insert(&mut self, k: Vec<u8>, v: Vec<u8>) {
-		let k = bytes_to_nibbles(&k);
+	// insert sets the value of a key. It fails if a node on the path to the key is missing or
+	// invalid, in which case the trie is left unchanged.
+	pub fn insert(&mut self, k: Vec<u8>, v: Vec<u8>) -> Result<(), MptError> {
+		// Reading the key loads every node the insert touches.
+		let previous = self.get(k.clone())?.map(|v| v.to_vec());
+		let nibbles = bytes_to_nibbles(&k);
 		let root = std::mem::take(&mut self.root);
-		self.root = root.insert(&k, v);
+		self.root = root.insert(&nibbles, v, &mut self.db)?;
+		if self.journal.is_active() {
+			self.journal.record(k, previous);
+		}
+		Ok(())
 	}
 
-	pub fn get(&self, k: Vec<u8>) -> Option<&[u8]> {
-		self.root.get(&bytes_to_nibbles(&k))
+	pub fn get(&self, k: Vec<u8>) -> Result<Option<&[u8]>, MptError> {
+		self.root.get(&bytes_to_nibbles(&k), &self.db)
+	}
+
+	// remove deletes a key from the trie and returns the value that was stored there.
+	// The trie is collapsed back into its canonical form so the root hash matches a trie
+	// that never contained the key.
+	pub fn remove(&mut self, k: Vec<u8>) -> Result<Option<Vec<u8>>, MptError> {
+		let nibbles = bytes_to_nibbles(&k);
+		// Load every node the removal touches first so that a missing node can't leave the
+		// trie half modified.
+		if self.get(k.clone())?.is_none() {
+			return Ok(None);
+		}
+		self.root.load_for_remove(&nibbles, &self.db)?;
+		let root = std::mem::take(&mut self.root);
+		let (root, removed) = root.remove(&nibbles, &mut self.db)?;
+		self.root = root;
+		if removed.is_some() && self.journal.is_active() {
+			self.journal.record(k, removed.clone());
+		}
+		Ok(removed)
 	}
 }
 
@@ -35,14 +157,20 @@ enum Node {
 	Branch(BranchNode),
 	Extension(ExtensionNode),
 	Value(ValueNode),
+	Hash(HashNode),
 }
 
 impl Node {
+	// new prefixes the child with the given nibbles. Extension children are merged into
+	// a single extension node so the result is always in canonical form.
 	fn new(nibbles: &[u8], child: Node) -> Self {
 		if nibbles.is_empty() {
-			child
-		} else {
-			ExtensionNode::new_node(nibbles.to_owned(), Box::new(child))
+			return child;
+		}
+		match child {
+			Node::Empty => Node::Empty,
+			Node::Extension(ext) => ExtensionNode::new_node([nibbles, &ext.nibbles].concat(), ext.child),
+			child => ExtensionNode::new_node(nibbles.to_owned(), Box::new(child)),
 		}
 	}
 
@@ -50,36 +178,149 @@ impl Node {
 		Node::Value(ValueNode::new(value))
 	}
 
-	fn insert(self, nibbles: &[u8], value: Vec<u8>) -> Self {
+	fn new_hash(hash: Hash) -> Self {
+		if hash == EMPTY_ROOT {
+			Node::Empty
+		} else {
+			Node::Hash(HashNode::new(hash))
+		}
+	}
+
+	// resolve loads the node if it is only a hash reference.
+	fn resolve(self, db: &NodeDb) -> Result<Self, MptError> {
+		match self {
+			Node::Hash(node) => node.into_node(db),
+			node => Ok(node),
+		}
+	}
+
+	fn insert(self, nibbles: &[u8], value: Vec<u8>, db: &mut NodeDb) -> Result<Self, MptError> {
+		match self {
+			Node::Empty => Ok(Node::new(nibbles, Node::new_value(value))),
+			Node::Branch(node) => node.insert(nibbles, value, db),
+			Node::Extension(node) => node.insert(nibbles, value, db),
+			Node::Value(mut node) => {
+				// The value is either replaced or moved into a branch, so its leaf encoding is stale.
+				db.release(node.encoded.take());
+				if nibbles.is_empty() {
+					Ok(Node::new_value(value))
+				} else {
+					BranchNode::new_with_value(node).insert(nibbles, value, db)
+				}
+			}
+			Node::Hash(node) => node.into_node(db)?.insert(nibbles, value, db),
+		}
+	}
+
+	fn get<'a>(&'a self, nibbles: &[u8], db: &NodeDb) -> Result<Option<&'a [u8]>, MptError> {
+		match self {
+			Node::Empty => Ok(None),
+			Node::Branch(node) => node.get(nibbles, db),
+			Node::Extension(node) => node.get(nibbles, db),
+			Node::Value(node) => Ok(node.get(nibbles)),
+			Node::Hash(node) => node.resolve(db)?.get(nibbles, db),
+		}
+	}
+
+	// remove deletes the value at nibbles & returns the new node along with the removed value.
+	fn remove(self, nibbles: &[u8], db: &mut NodeDb) -> Result<(Self, Option<Vec<u8>>), MptError> {
 		match self {
-			Node::Empty => Node::new(nibbles, Node::new_value(value)),
-			Node::Branch(node) => node.insert(nibbles, value),
-			Node::Extension(node) => node.insert(nibbles, value),
-			Node::Value(node) => {
+			Node::Empty => Ok((Node::Empty, None)),
+			Node::Branch(node) => node.remove(nibbles, db),
+			Node::Extension(node) => node.remove(nibbles, db),
+			Node::Value(mut node) => {
 				if nibbles.is_empty() {
-					Node::new_value(value)
+					db.release(node.encoded.take());
+					Ok((Node::Empty, Some(node.value)))
 				} else {
-					BranchNode::new_with_value(node).insert(nibbles, value)
+					Ok((Node::Value(node), None))
+				}
+			}
+			Node::Hash(node) => node.into_node(db)?.remove(nibbles, db),
+		}
+	}
+
+	// load_for_remove loads the nodes that removing the key at nibbles will collapse into their
+	// parent, i.e. the only other child of a branch that is left with one child. It returns
+	// true if the node is left empty. The path to the key must already be loaded.
+	fn load_for_remove(&self, nibbles: &[u8], db: &NodeDb) -> Result<bool, MptError> {
+		match self {
+			Node::Empty => Ok(true),
+			Node::Value(..) => Ok(nibbles.is_empty()),
+			Node::Hash(node) => node.resolve(db)?.load_for_remove(nibbles, db),
+			Node::Extension(node) => match nibbles.strip_prefix(&node.nibbles[..]) {
+				Some(rest) => node.child.load_for_remove(rest, db),
+				None => Ok(false),
+			},
+			Node::Branch(node) => {
+				// The child on the path may be left empty, or the path may end at the branch value.
+				let emptied = match nibbles.split_first() {
+					None => None,
+					Some((&i, rest)) => node.children[i as usize].load_for_remove(rest, db)?.then_some(i as usize),
+				};
+				let value_left = !nibbles.is_empty() && node.branch_value.is_some();
+				let mut children = node
+					.children
+					.iter()
+					.enumerate()
+					.filter(|(i, c)| !matches!(c.as_ref(), Node::Empty) && Some(*i) != emptied);
+				if let (Some((_, child)), None, false) = (children.next(), children.next(), value_left) {
+					if let Node::Hash(child) = child.as_ref() {
+						child.resolve(db)?;
+					}
 				}
+				Ok(false)
 			}
 		}
 	}
 
-	fn get(&self, nibbles: &[u8]) -> Option<&[u8]> {
+	// encoded returns the cached encoding of the node, encoding it first if it changed.
+	fn encoded(&mut self, db: &mut NodeDb) -> Option<&Encoded> {
 		match self {
 			Node::Empty => None,
-			Node::Branch(node) => node.get(nibbles),
-			Node::Extension(node) => node.get(nibbles),
-			Node::Value(node) => node.get(nibbles),
+			Node::Branch(node) => Some(node.encoded(db)),
+			Node::Extension(node) => Some(node.encoded(db)),
+			Node::Value(node) => Some(node.encoded(db)),
+			Node::Hash(node) => node
+				.resolved
+				.get_mut()
+				.expect("hash node must be resolved to be encoded")
+				.encoded(db),
+		}
+	}
+
+	fn rlp_bytes(&mut self, db: &mut NodeDb) -> Vec<u8> {
+		match self.encoded(db) {
+			Some(encoded) => encoded.rlp.clone(),
+			None => vec![0x80],
+		}
+	}
+
+	fn hash(&mut self, db: &mut NodeDb) -> Hash {
+		match self {
+			Node::Empty => EMPTY_ROOT,
+			Node::Hash(node) => node.hash,
+			node => node.encoded(db).unwrap().hash,
 		}
 	}
 
-	fn rlp_bytes(&mut self, db: &mut HashMap<Hash, Vec<u8>>) -> Vec<u8> {
+	// reference returns how a parent node refers to this node: by hash, or by the
+	// node's encoding if it is shorter than 32 bytes.
+	fn reference(&mut self, db: &mut NodeDb) -> RLPEncodeableWrapper {
 		match self {
-			Node::Empty => vec![0x80],
-			Node::Branch(node) => node.rlp_bytes(db),
-			Node::Extension(node) => node.rlp_bytes(db),
-			Node::Value(node) => node.rlp_bytes(db),
+			Node::Empty => RLPEncodeableWrapper::EmptyString,
+			Node::Hash(node) => RLPEncodeableWrapper::Bytes(node.hash.to_vec()),
+			node => node.encoded(db).unwrap().reference(),
+		}
+	}
+
+	// release drops the cached encoding of a node that is about to change.
+	fn release(&mut self, db: &mut NodeDb) {
+		match self {
+			Node::Branch(node) => db.release(node.encoded.take()),
+			Node::Extension(node) => db.release(node.encoded.take()),
+			Node::Value(node) => db.release(node.encoded.take()),
+			Node::Empty | Node::Hash(..) => (),
 		}
 	}
 }
@@ -94,26 +335,71 @@ impl Default for Node {
 struct BranchNode {
 	children: [Box<Node>; 16],
 	branch_value: Option<ValueNode>,
+	encoded: Option<Encoded>,
 }
 
 impl BranchNode {
 	// inserts adds a key/value to a branch node as either a sub-node or as a value.
-	// It returns none if there is an error.
-	fn insert(mut self, nibbles: &[u8], value: Vec<u8>) -> Node {
+	fn insert(mut self, nibbles: &[u8], value: Vec<u8>, db: &mut NodeDb) -> Result<Node, MptError> {
+		db.release(self.encoded.take());
 		if nibbles.is_empty() {
 			self.branch_value = Some(ValueNode::new(value));
 		} else {
 			let i = nibbles[0] as usize;
-			*self.children[i] = std::mem::take(&mut self.children[i]).insert(&nibbles[1..], value);
+			*self.children[i] = std::mem::take(&mut self.children[i]).insert(&nibbles[1..], value, db)?;
 		};
-		self.into()
+		Ok(self.into())
 	}
 
-	fn get(&self, nibbles: &[u8]) -> Option<&[u8]> {
+	fn get<'a>(&'a self, nibbles: &[u8], db: &NodeDb) -> Result<Option<&'a [u8]>, MptError> {
 		if nibbles.is_empty() {
-			self.branch_value.as_ref().map(|v| &v.value[..])
+			Ok(self.branch_value.as_ref().map(|v| &v.value[..]))
+		} else {
+			self.children[nibbles[0] as usize].get(&nibbles[1..], db)
+		}
+	}
+
+	fn remove(mut self, nibbles: &[u8], db: &mut NodeDb) -> Result<(Node, Option<Vec<u8>>), MptError> {
+		let removed = if nibbles.is_empty() {
+			self.branch_value.take().map(|v| v.value)
 		} else {
-			self.children[nibbles[0] as usize].get(&nibbles[1..])
+			let i = nibbles[0] as usize;
+			let (child, removed) = std::mem::take(&mut self.children[i]).remove(&nibbles[1..], db)?;
+			*self.children[i] = child;
+			removed
+		};
+		if removed.is_none() {
+			return Ok((self.into(), None));
+		}
+		db.release(self.encoded.take());
+		Ok((self.collapse(db)?, removed))
+	}
+
+	// collapse turns a branch node that is left with a single child or only a value
+	// into the equivalent leaf or extension node.
+	fn collapse(mut self, db: &mut NodeDb) -> Result<Node, MptError> {
+		let mut children = self.children.iter().enumerate().filter(|(_, c)| !matches!(c.as_ref(), Node::Empty));
+		let only_child = match (children.next(), children.next()) {
+			(None, _) => None,
+			(Some((i, _)), None) => Some(i),
+			_ => return Ok(self.into()),
+		};
+		match (only_child, self.branch_value.take()) {
+			(None, None) => Ok(Node::Empty),
+			(None, Some(value)) => Ok(Node::Value(value)),
+			// The child must be loaded to know if it is an extension that has to be merged.
+			(Some(i), None) => {
+				let mut child = std::mem::take(&mut *self.children[i]).resolve(db)?;
+				// A branch keeps its encoding, but leaves & extensions get a longer path.
+				if !matches!(child, Node::Branch(..)) {
+					child.release(db);
+				}
+				Ok(Node::new(&[i as u8], child))
+			}
+			(Some(_), Some(value)) => {
+				self.branch_value = Some(value);
+				Ok(self.into())
+			}
 		}
 	}
 
@@ -132,14 +418,22 @@ impl BranchNode {
 		}
 	}
 
-	fn rlp_bytes(&mut self, db: &mut HashMap<Hash, Vec<u8>>) -> Vec<u8> {
+	fn encoded(&mut self, db: &mut NodeDb) -> &Encoded {
+		if self.encoded.is_none() {
+			let rlp = self.rlp_bytes(db);
+			self.encoded = Some(db.encode(rlp));
+		}
+		self.encoded.as_ref().unwrap()
+	}
+
+	fn rlp_bytes(&mut self, db: &mut NodeDb) -> Vec<u8> {
 		let mut list: Vec<RLPEncodeableWrapper> = Vec::new();
 		let mut bytes = Vec::new();
 		for child in self.children.iter_mut() {
-			list.push(mpt_hash(&child.rlp_bytes(db), db));
+			list.push(child.reference(db));
 		}
 		match &self.branch_value {
-			Some(value) => list.push(mpt_hash(&value.rlp_bytes(db), db)),
+			Some(value) => list.push(RLPEncodeableWrapper::Bytes(value.value.clone())),
 			None => list.push(RLPEncodeableWrapper::EmptyString),
 		}
 		reth_rlp::encode_list(&list, &mut bytes);
@@ -156,62 +450,98 @@ impl From<BranchNode> for Node {
 struct ExtensionNode {
 	nibbles: Vec<u8>,
 	child: Box<Node>,
+	encoded: Option<Encoded>,
 }
 
 impl ExtensionNode {
 	fn new_node(nibbles: Vec<u8>, child: Box<Node>) -> Node {
-		Node::Extension(Self { nibbles, child })
+		Node::Extension(Self {
+			nibbles,
+			child,
+			encoded: None,
+		})
 	}
 
+	// compact encodes the path of the node. Extensions that point to a value are leaves. Nodes
+	// are checked when they are decoded & loaded, so the child is never empty or an extension.
 	fn compact(&self) -> Vec<u8> {
-		let extension = match *self.child {
-			Node::Empty => panic!("Cannot point to an empty node in an extension"),
-			Node::Extension(..) => panic!("Cannot point to an extension node in an extension node"),
-			Node::Value(..) => false,
-			Node::Branch(..) => true,
-		};
+		let extension = !matches!(*self.child, Node::Value(..));
 		nibbles_to_compact(&self.nibbles, extension)
 	}
 
-	fn insert(self, nibbles: &[u8], value: Vec<u8>) -> Node {
+	fn insert(mut self, nibbles: &[u8], value: Vec<u8>, db: &mut NodeDb) -> Result<Node, MptError> {
+		db.release(self.encoded.take());
 		let (common, new_nibbles, old_nibbles) = match_paths(nibbles, &self.nibbles);
 		if new_nibbles.is_empty() && old_nibbles.is_empty() {
-			return ExtensionNode::new_node(common, Box::new(self.child.insert(&[], value)));
+			return Ok(ExtensionNode::new_node(common, Box::new(self.child.insert(&[], value, db)?)));
 		}
 		// Inserting here will alwasy create branch node.
 		// Turn the existing node into that branch node then insert the new value.
 		let branch_node = if old_nibbles.is_empty() {
-			match *self.child {
-				Node::Empty => panic!("Cannot point to an empty node in an extension"),
-				Node::Extension(..) => panic!("Cannot point to an extension node in an extension node"),
+			match self.child.resolve(db)? {
 				Node::Value(child) => BranchNode::new_with_value(child),
 				Node::Branch(child) => child,
+				Node::Empty | Node::Extension(..) | Node::Hash(..) => return Err(MptError::InvalidExtension),
 			}
 		} else {
 			let child = Box::new(Node::new(&old_nibbles[1..], *(self.child)));
 			BranchNode::new_with_node(old_nibbles[0], child)
 		}
-		.insert(new_nibbles, value);
+		.insert(new_nibbles, value, db)?;
 		// Create an extension node based on the common part if needed.
 		if common.is_empty() {
-			branch_node
+			Ok(branch_node)
 		} else {
-			ExtensionNode::new_node(common, Box::new(branch_node))
+			Ok(ExtensionNode::new_node(common, Box::new(branch_node)))
 		}
 	}
 
-	fn get(&self, nibbles: &[u8]) -> Option<&[u8]> {
+	fn get<'a>(&'a self, nibbles: &[u8], db: &NodeDb) -> Result<Option<&'a [u8]>, MptError> {
 		let (_, new_nibbles, old_nibbles) = match_paths(nibbles, &self.nibbles);
-		if old_nibbles.is_empty() {
-			self.child.get(new_nibbles)
-		} else {
-			None
+		if !old_nibbles.is_empty() {
+			return Ok(None);
+		}
+		match self.child.as_ref() {
+			// Only branch nodes are large enough to be referenced by hash from an extension.
+			Node::Hash(child) => match child.resolve(db)? {
+				child @ Node::Branch(..) => child.get(new_nibbles, db),
+				_ => Err(MptError::InvalidExtension),
+			},
+			child => child.get(new_nibbles, db),
+		}
+	}
+
+	fn remove(mut self, nibbles: &[u8], db: &mut NodeDb) -> Result<(Node, Option<Vec<u8>>), MptError> {
+		let (_, new_nibbles, old_nibbles) = match_paths(nibbles, &self.nibbles);
+		if !old_nibbles.is_empty() {
+			return Ok((Node::Extension(self), None));
 		}
+		let (child, removed) = std::mem::take(&mut *self.child).remove(new_nibbles, db)?;
+		if removed.is_none() {
+			*self.child = child;
+			return Ok((Node::Extension(self), None));
+		}
+		db.release(self.encoded.take());
+		// The child may have collapsed into an extension or disappeared entirely.
+		Ok((Node::new(&self.nibbles, child), removed))
 	}
 
-	fn rlp_bytes(&mut self, db: &mut HashMap<Hash, Vec<u8>>) -> Vec<u8> {
+	fn encoded(&mut self, db: &mut NodeDb) -> &Encoded {
+		if self.encoded.is_none() {
+			let rlp = self.rlp_bytes(db);
+			self.encoded = Some(db.encode(rlp));
+		}
+		self.encoded.as_ref().unwrap()
+	}
+
+	fn rlp_bytes(&mut self, db: &mut NodeDb) -> Vec<u8> {
 		let mut bytes = Vec::new();
-		let list = vec![RLPEncodeableWrapper::Bytes(self.compact()), mpt_hash(&self.child.rlp_bytes(db), db)];
+		// Leaf values are stored in the node itself rather than as a reference to a sub-node.
+		let child = match self.child.as_mut() {
+			Node::Value(node) => RLPEncodeableWrapper::Bytes(node.value.clone()),
+			child => child.reference(db),
+		};
+		let list = vec![RLPEncodeableWrapper::Bytes(self.compact()), child];
 		reth_rlp::encode_list(&list, &mut bytes);
 		bytes
 	}
@@ -219,11 +549,14 @@ impl ExtensionNode {
 
 struct ValueNode {
 	value: Vec<u8>,
+	// encoded is only set when the value is a leaf of its own, not when it is stored in a
+	// branch or behind an extension.
+	encoded: Option<Encoded>,
 }
 
 impl ValueNode {
 	fn new(value: Vec<u8>) -> Self {
-		Self { value }
+		Self { value, encoded: None }
 	}
 	fn get(&self, _nibbles: &[u8]) -> Option<&[u8]> {
 		if _nibbles.is_empty() {
@@ -235,7 +568,64 @@ impl ValueNode {
 		// // It did not b/c I did not fuzz by querying with known missing keys.
 		// Some(&self.value)
 	}
-	fn rlp_bytes(&self, _: &mut HashMap<Hash, Vec<u8>>) -> Vec<u8> {
-		encode_bytes(self.value.clone())
+	fn encoded(&mut self, db: &mut NodeDb) -> &Encoded {
+		if self.encoded.is_none() {
+			let rlp = self.rlp_bytes();
+			self.encoded = Some(db.encode(rlp));
+		}
+		self.encoded.as_ref().unwrap()
+	}
+
+	// rlp_bytes encodes a value node that is not behind an extension as a leaf with an empty path.
+	fn rlp_bytes(&self) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		let list = vec![
+			RLPEncodeableWrapper::Bytes(nibbles_to_compact(&[], false)),
+			RLPEncodeableWrapper::Bytes(self.value.clone()),
+		];
+		reth_rlp::encode_list(&list, &mut bytes);
+		bytes
+	}
+}
+
+// HashNode is a node that is only known by its hash. It is loaded from the trie's
+// NodeSource the first time a path through it is read or modified.
+struct HashNode {
+	hash: Hash,
+	resolved: OnceCell<Box<Node>>,
+}
+
+impl HashNode {
+	fn new(hash: Hash) -> Self {
+		Self {
+			hash,
+			resolved: OnceCell::new(),
+		}
+	}
+
+	// resolve loads & decodes the node. The decoded node is kept so it is only loaded once.
+	fn resolve(&self, source: &dyn NodeSource) -> Result<&Node, MptError> {
+		if let Some(node) = self.resolved.get() {
+			return Ok(node);
+		}
+		let node = Self::load(self.hash, source)?;
+		Ok(self.resolved.get_or_init(|| Box::new(node)))
+	}
+
+	fn into_node(self, source: &dyn NodeSource) -> Result<Node, MptError> {
+		match self.resolved.into_inner() {
+			Some(node) => Ok(*node),
+			None => Self::load(self.hash, source),
+		}
+	}
+
+	// load fetches the node from the source. The source is not trusted, so the node must hash to
+	// the hash it was requested by before it is decoded.
+	fn load(hash: Hash, source: &dyn NodeSource) -> Result<Node, MptError> {
+		let encoded = source.get_node(&hash).ok_or(MptError::MissingNode(hash))?;
+		if keccak(&encoded) != hash {
+			return Err(MptError::HashMismatch(hash));
+		}
+		Ok(Node::decode(&encoded)?)
 	}
 }
@@ -1,7 +1,7 @@
-use core::types::{keccak, Hash};
+use crate::MptError;
 use reth_primitives::Bytes;
 use reth_rlp::Encodable;
-use std::{collections::HashMap, fmt::Debug, iter::zip};
+use std::{fmt::Debug, iter::zip};
 
 #[derive(Debug)]
 pub enum RLPEncodeableWrapper {
@@ -28,17 +28,6 @@ pub fn encode_bytes(x: Vec<u8>) -> Vec<u8> {
 	out
 }
 
-// mpt_hash implements H(x) as used in the MPT.
-pub fn mpt_hash(x: &[u8], db: &mut HashMap<Hash, Vec<u8>>) -> RLPEncodeableWrapper {
-	if x.len() < 32 {
-		RLPEncodeableWrapper::Raw(x.to_vec())
-	} else {
-		let h = keccak(x);
-		db.insert(h, x.to_vec());
-		RLPEncodeableWrapper::Bytes(h.to_vec())
-	}
-}
-
 // match_paths is a helper function that returns the shared bytes between a & b as well
 // the remaining bytes in a & b.
 pub fn match_paths<'a, 'b>(key: &'a [u8], path: &'b [u8]) -> (Vec<u8>, &'a [u8], &'b [u8]) {
@@ -90,14 +79,14 @@ pub fn nibbles_to_compact(nibbles: &[u8], extension: bool) -> Vec<u8> {
 
 // compact_to_nibbles decodes Ethereum's compact encoding into the original nibbles
 // array and also returns if the path was an extension or not.
-#[allow(dead_code)]
-pub fn compact_to_nibbles(compact: &[u8]) -> (Vec<u8>, bool) {
-	let (extension, even) = match compact[0] >> 4 {
+pub fn compact_to_nibbles(compact: &[u8]) -> Result<(Vec<u8>, bool), MptError> {
+	let first = *compact.first().ok_or(MptError::InvalidPath)?;
+	let (extension, even) = match first >> 4 {
 		0 => (true, true),
 		1 => (true, false),
 		2 => (false, true),
 		3 => (false, false),
-		_ => panic!("out of range"),
+		_ => return Err(MptError::InvalidPath),
 	};
 	let mut nibbles = Vec::new();
 	if !even {
@@ -107,5 +96,10 @@ pub fn compact_to_nibbles(compact: &[u8]) -> (Vec<u8>, bool) {
 		nibbles.push(b >> 4);
 		nibbles.push(b & 0x0f);
 	}
-	(nibbles, extension)
+	Ok((nibbles, extension))
+}
+
+// nibbles_to_bytes folds a list of nibbles back into the bytes they were split from.
+pub fn nibbles_to_bytes(nibbles: &[u8]) -> Vec<u8> {
+	nibbles.chunks_exact(2).map(|a| a[0] << 4 | a[1]).collect()
 }
@@ -0,0 +1,45 @@
+use crate::{StackTrie, MPT};
+use core::types::Hash;
+use reth_rlp::Encodable;
+use std::collections::HashMap;
+
+// ordered_trie_root computes the root of a trie that maps rlp(index) to each item. This is how
+// the transactions, receipts & withdrawals roots of a block are built. It also returns the
+// pre-images of the trie's nodes so that items can be looked up or proven by the root.
+pub fn ordered_trie_root(items: impl Iterator<Item = Vec<u8>>) -> (Hash, HashMap<Hash, Vec<u8>>) {
+	let mut mpt = MPT::default();
+	for (i, item) in items.enumerate() {
+		mpt.insert(ordered_trie_key(i), item).expect("in-memory trie has every node");
+	}
+	let root = mpt.hash();
+	(root, std::mem::take(&mut mpt.db.nodes))
+}
+
+// ordered_trie_hash computes the same root as ordered_trie_root without building the trie. The
+// keys are inserted in sorted order: rlp(0) = 0x80 sorts after the keys of items 1 to 127, which
+// are single bytes, & before the longer keys of the items after them.
+pub fn ordered_trie_hash(items: impl Iterator<Item = Vec<u8>>) -> Hash {
+	let mut trie = StackTrie::new();
+	let mut first = None;
+	for (i, item) in items.enumerate() {
+		if i == 0 {
+			first = Some(item);
+			continue;
+		}
+		if i == 128 {
+			trie.insert(ordered_trie_key(0), first.take().unwrap()).expect("keys are sorted");
+		}
+		trie.insert(ordered_trie_key(i), item).expect("keys are sorted");
+	}
+	if let Some(item) = first {
+		trie.insert(ordered_trie_key(0), item).expect("keys are sorted");
+	}
+	trie.hash()
+}
+
+// ordered_trie_key returns the key of the item at index in an ordered trie.
+pub fn ordered_trie_key(index: usize) -> Vec<u8> {
+	let mut key = Vec::new();
+	(index as u64).encode(&mut key);
+	key
+}
@@ -0,0 +1,329 @@
+use crate::db::NodeDb;
+use crate::decode::{decode_node, NodeRef, RawNode};
+use crate::misc::*;
+use crate::{MptError, Node, EMPTY_ROOT, MPT};
+use core::types::{keccak, Hash};
+use reth_rlp::DecodeError;
+use std::{
+	collections::{HashMap, HashSet},
+	fmt::Display,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProofError {
+	/// A node referenced by hash is not in the proof.
+	MissingNode(Hash),
+	/// The hash of a proof node does not match the reference in its parent.
+	HashMismatch { expected: Hash, actual: Hash },
+	/// A proof node is not a valid trie node.
+	InvalidNode(DecodeError),
+	/// The proof contains nodes that are not on the path to any of the keys.
+	UnusedNodes,
+}
+
+impl Display for ProofError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ProofError::MissingNode(hash) => write!(f, "proof is missing node {hash:?}"),
+			ProofError::HashMismatch { expected, actual } => write!(f, "expected node {expected:?}, got {actual:?}"),
+			ProofError::InvalidNode(err) => write!(f, "invalid proof node: {err}"),
+			ProofError::UnusedNodes => write!(f, "proof contains unused nodes"),
+		}
+	}
+}
+
+impl std::error::Error for ProofError {}
+
+impl From<DecodeError> for ProofError {
+	fn from(err: DecodeError) -> Self {
+		ProofError::InvalidNode(err)
+	}
+}
+
+// verify_proof checks a proof generated by `MPT::prove` against the root & returns the value
+// stored at the key. It returns None if the proof shows that the key is not in the trie.
+pub fn verify_proof(root: Hash, key: &[u8], proof: &[Vec<u8>]) -> Result<Option<Vec<u8>>, ProofError> {
+	if proof.is_empty() && root == EMPTY_ROOT {
+		return Ok(None);
+	}
+	let mut nibbles = &bytes_to_nibbles(key)[..];
+	let mut proof = proof.iter();
+	let mut next = NodeRef::Hash(root);
+	let value = loop {
+		let node = match next {
+			NodeRef::Empty => break None,
+			NodeRef::Inline(node) => node,
+			NodeRef::Hash(expected) => {
+				let node = proof.next().ok_or(ProofError::MissingNode(expected))?;
+				let actual = keccak(node);
+				if actual != expected {
+					return Err(ProofError::HashMismatch { expected, actual });
+				}
+				node
+			}
+		};
+		match decode_node(node)? {
+			RawNode::Empty => break None,
+			RawNode::Branch { value, .. } if nibbles.is_empty() => break value.map(|v| v.to_vec()),
+			RawNode::Branch { mut children, .. } => {
+				next = std::mem::replace(&mut children[nibbles[0] as usize], NodeRef::Empty);
+				nibbles = &nibbles[1..];
+			}
+			RawNode::Extension { nibbles: path, child } => match nibbles.strip_prefix(&path[..]) {
+				Some(rest) => {
+					next = child;
+					nibbles = rest;
+				}
+				None => break None,
+			},
+			RawNode::Leaf { nibbles: path, value } => break (path == nibbles).then(|| value.to_vec()),
+		}
+	};
+	if proof.next().is_some() {
+		return Err(ProofError::UnusedNodes);
+	}
+	Ok(value)
+}
+
+// verify_multiproof checks a proof generated by `MPT::prove_multi` against the root & returns
+// the value stored at each of the keys, in the same order as the keys.
+pub fn verify_multiproof(root: Hash, keys: &[Vec<u8>], proof: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>, ProofError> {
+	let mut nodes = ProofNodes::new(root, proof);
+	let values = keys.iter().map(|k| nodes.lookup(&bytes_to_nibbles(k))).collect::<Result<_, _>>()?;
+	nodes.finish()?;
+	Ok(values)
+}
+
+// verify_range_proof checks a proof generated by `MPT::prove_range` against the root & returns
+// every key & value in the range start..end, in key order. The proof must contain every node
+// that may hold keys in the range, so no key can be left out.
+pub fn verify_range_proof(root: Hash, start: &[u8], end: &[u8], proof: &[Vec<u8>]) -> Result<Entries, ProofError> {
+	let (start, end) = (bytes_to_nibbles(start), bytes_to_nibbles(end));
+	let mut nodes = ProofNodes::new(root, proof);
+	let mut entries = Vec::new();
+	nodes.walk_range(NodeRef::Hash(root), Vec::new(), &start, &end, &mut entries)?;
+	nodes.finish()?;
+	Ok(entries)
+}
+
+// Entries are the keys & values of a range proof.
+type Entries = Vec<(Vec<u8>, Vec<u8>)>;
+
+// ProofNodes holds the nodes of a proof by hash so that paths to several keys can share nodes.
+struct ProofNodes<'a> {
+	root: Hash,
+	nodes: HashMap<Hash, &'a [u8]>,
+	used: HashSet<Hash>,
+}
+
+impl<'a> ProofNodes<'a> {
+	fn new(root: Hash, proof: &'a [Vec<u8>]) -> Self {
+		Self {
+			root,
+			nodes: proof.iter().map(|node| (keccak(node), &node[..])).collect(),
+			used: HashSet::new(),
+		}
+	}
+
+	// get returns the encoding of the referenced node. The empty trie has no nodes, so its
+	// root is treated as an empty reference.
+	fn get(&mut self, node: NodeRef<'a>) -> Result<Option<&'a [u8]>, ProofError> {
+		match node {
+			NodeRef::Empty => Ok(None),
+			NodeRef::Hash(hash) if hash == self.root && hash == EMPTY_ROOT => Ok(None),
+			NodeRef::Inline(node) => Ok(Some(node)),
+			NodeRef::Hash(hash) => {
+				let node = self.nodes.get(&hash).ok_or(ProofError::MissingNode(hash))?;
+				self.used.insert(hash);
+				Ok(Some(node))
+			}
+		}
+	}
+
+	fn lookup(&mut self, mut nibbles: &[u8]) -> Result<Option<Vec<u8>>, ProofError> {
+		let mut next = NodeRef::Hash(self.root);
+		loop {
+			let Some(node) = self.get(next)? else {
+				return Ok(None);
+			};
+			match decode_node(node)? {
+				RawNode::Empty => return Ok(None),
+				RawNode::Branch { value, .. } if nibbles.is_empty() => return Ok(value.map(|v| v.to_vec())),
+				RawNode::Branch { mut children, .. } => {
+					next = std::mem::replace(&mut children[nibbles[0] as usize], NodeRef::Empty);
+					nibbles = &nibbles[1..];
+				}
+				RawNode::Extension { nibbles: path, child } => match nibbles.strip_prefix(&path[..]) {
+					Some(rest) => {
+						next = child;
+						nibbles = rest;
+					}
+					None => return Ok(None),
+				},
+				RawNode::Leaf { nibbles: path, value } => return Ok((path == nibbles).then(|| value.to_vec())),
+			}
+		}
+	}
+
+	// walk_range appends every entry in start..end under the node at path to entries.
+	fn walk_range(
+		&mut self,
+		node: NodeRef<'a>,
+		path: Vec<u8>,
+		start: &[u8],
+		end: &[u8],
+		entries: &mut Entries,
+	) -> Result<(), ProofError> {
+		if !intersects(&path, start, end) {
+			return Ok(());
+		}
+		let Some(node) = self.get(node)? else {
+			return Ok(());
+		};
+		let in_range = |key: &[u8]| key >= start && key < end;
+		match decode_node(node)? {
+			RawNode::Empty => (),
+			RawNode::Branch { children, value } => {
+				if let Some(value) = value.filter(|_| in_range(&path)) {
+					entries.push((nibbles_to_bytes(&path), value.to_vec()));
+				}
+				for (i, child) in children.into_iter().enumerate() {
+					self.walk_range(child, [&path[..], &[i as u8]].concat(), start, end, entries)?;
+				}
+			}
+			RawNode::Extension { nibbles, child } => self.walk_range(child, [path, nibbles].concat(), start, end, entries)?,
+			RawNode::Leaf { nibbles, value } => {
+				let key = [path, nibbles].concat();
+				if in_range(&key) {
+					entries.push((nibbles_to_bytes(&key), value.to_vec()));
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn finish(self) -> Result<(), ProofError> {
+		if self.used.len() != self.nodes.len() {
+			return Err(ProofError::UnusedNodes);
+		}
+		Ok(())
+	}
+}
+
+// intersects returns true if any key under path may fall in the range start..end.
+fn intersects(path: &[u8], start: &[u8], end: &[u8]) -> bool {
+	let n = path.len().min(start.len());
+	path[..n] >= start[..n] && path < end
+}
+
+impl MPT {
+	// prove_multi returns a single proof for all of the keys. Nodes that are shared by the
+	// paths to several keys are only included once.
+	pub fn prove_multi(&mut self, keys: &[Vec<u8>]) -> Result<Vec<Vec<u8>>, MptError> {
+		let mut seen = HashSet::new();
+		let mut proof = Vec::new();
+		for k in keys {
+			for node in self.prove(k.clone())? {
+				if seen.insert(keccak(&node)) {
+					proof.push(node);
+				}
+			}
+		}
+		Ok(proof)
+	}
+
+	// prove_range returns the nodes that hold every key in the range start..end along with the
+	// nodes on the paths to the ends of the range. This proves both the values in the range &
+	// that there are no other keys in it.
+	pub fn prove_range(&mut self, start: &[u8], end: &[u8]) -> Result<Vec<Vec<u8>>, MptError> {
+		let mut proof = Vec::new();
+		self.root.prove_range(
+			Vec::new(),
+			&bytes_to_nibbles(start),
+			&bytes_to_nibbles(end),
+			&mut self.db,
+			&mut proof,
+		)?;
+		Ok(proof.into_iter()
+			.enumerate()
+			.filter(|(i, node)| *i == 0 || node.len() >= 32)
+			.map(|(_, node)| node)
+			.collect())
+	}
+
+	// prove returns the RLP encoded nodes on the path from the root to the key.
+	// The proof only contains the root node & nodes that are referenced by hash. Nodes that
+	// are shorter than 32 bytes are already inlined in their parent's encoding.
+	// If the key is not present, the returned nodes prove its absence.
+	pub fn prove(&mut self, k: Vec<u8>) -> Result<Vec<Vec<u8>>, MptError> {
+		let mut proof = Vec::new();
+		self.root.prove(&bytes_to_nibbles(&k), &mut self.db, &mut proof)?;
+		Ok(proof.into_iter()
+			.enumerate()
+			.filter(|(i, node)| *i == 0 || node.len() >= 32)
+			.map(|(_, node)| node)
+			.collect())
+	}
+}
+
+impl Node {
+	// prove appends the encoding of every node on the path to nibbles to the proof.
+	fn prove(&mut self, nibbles: &[u8], db: &mut NodeDb, proof: &mut Vec<Vec<u8>>) -> Result<(), MptError> {
+		match self {
+			Node::Empty => return Ok(()),
+			Node::Hash(node) => {
+				node.resolve(db)?;
+				return node.resolved.get_mut().unwrap().prove(nibbles, db, proof);
+			}
+			_ => proof.push(self.rlp_bytes(db)),
+		}
+		match self {
+			Node::Branch(node) if !nibbles.is_empty() => node.children[nibbles[0] as usize].prove(&nibbles[1..], db, proof),
+			Node::Extension(node) => {
+				let (_, new_nibbles, old_nibbles) = match_paths(nibbles, &node.nibbles);
+				// An extension pointing to a value is a leaf, so there is nothing left to walk.
+				if old_nibbles.is_empty() && !matches!(*node.child, Node::Value(..)) {
+					node.child.prove(new_nibbles, db, proof)
+				} else {
+					Ok(())
+				}
+			}
+			_ => Ok(()),
+		}
+	}
+
+	// prove_range appends the encoding of every node that may hold keys in start..end to the proof.
+	fn prove_range(
+		&mut self,
+		path: Vec<u8>,
+		start: &[u8],
+		end: &[u8],
+		db: &mut NodeDb,
+		proof: &mut Vec<Vec<u8>>,
+	) -> Result<(), MptError> {
+		if !intersects(&path, start, end) {
+			return Ok(());
+		}
+		match self {
+			Node::Empty => return Ok(()),
+			Node::Hash(node) => {
+				node.resolve(db)?;
+				return node.resolved.get_mut().unwrap().prove_range(path, start, end, db, proof);
+			}
+			_ => proof.push(self.rlp_bytes(db)),
+		}
+		match self {
+			Node::Branch(node) => {
+				for (i, child) in node.children.iter_mut().enumerate() {
+					child.prove_range([&path[..], &[i as u8]].concat(), start, end, db, proof)?;
+				}
+			}
+			Node::Extension(node) if !matches!(*node.child, Node::Value(..)) => {
+				node.child
+					.prove_range([&path[..], &node.nibbles[..]].concat(), start, end, db, proof)?
+			}
+			_ => (),
+		}
+		Ok(())
+	}
+}
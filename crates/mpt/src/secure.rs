@@ -0,0 +1,67 @@
+use crate::{MptError, NodeSource, MPT};
+use core::types::{keccak, Hash};
+use std::collections::HashMap;
+
+// SecureMPT is a trie that stores every value under the keccak hash of its key. This is how
+// the Ethereum state & storage tries are keyed. It can optionally keep the pre-images of the
+// hashed keys so the original keys can be recovered.
+#[derive(Debug, Default)]
+pub struct SecureMPT {
+	mpt: MPT,
+	preimages: Option<HashMap<Hash, Vec<u8>>>,
+}
+
+impl SecureMPT {
+	// with_preimages creates a trie that remembers the original key of every hashed key.
+	pub fn with_preimages() -> Self {
+		Self {
+			mpt: MPT::default(),
+			preimages: Some(HashMap::new()),
+		}
+	}
+
+	// with_source creates a trie that loads its nodes from the source. See MPT::with_source.
+	pub fn with_source(root: Hash, source: impl NodeSource + 'static) -> Self {
+		Self {
+			mpt: MPT::with_source(root, source),
+			preimages: None,
+		}
+	}
+
+	pub fn hash(&mut self) -> Hash {
+		self.mpt.hash()
+	}
+
+	pub fn insert(&mut self, k: Vec<u8>, v: Vec<u8>) -> Result<(), MptError> {
+		let hashed = keccak(&k);
+		self.mpt.insert(hashed.to_vec(), v)?;
+		if let Some(preimages) = self.preimages.as_mut() {
+			preimages.insert(hashed, k);
+		}
+		Ok(())
+	}
+
+	pub fn get(&self, k: Vec<u8>) -> Result<Option<&[u8]>, MptError> {
+		self.mpt.get(keccak(k).to_vec())
+	}
+
+	pub fn remove(&mut self, k: Vec<u8>) -> Result<Option<Vec<u8>>, MptError> {
+		self.mpt.remove(keccak(k).to_vec())
+	}
+
+	// prove returns a proof for the hashed key. See MPT::prove.
+	pub fn prove(&mut self, k: Vec<u8>) -> Result<Vec<Vec<u8>>, MptError> {
+		self.mpt.prove(keccak(k).to_vec())
+	}
+
+	// preimage returns the original key of a hashed key if pre-images are being kept.
+	pub fn preimage(&self, hashed: &Hash) -> Option<&[u8]> {
+		self.preimages.as_ref()?.get(hashed).map(|k| &k[..])
+	}
+}
+
+impl From<MPT> for SecureMPT {
+	fn from(mpt: MPT) -> Self {
+		Self { mpt, preimages: None }
+	}
+}
@@ -0,0 +1,28 @@
+use core::types::Hash;
+use std::{collections::HashMap, rc::Rc};
+
+// NodeSource provides the RLP encoding of trie nodes by their hash. This is how a trie that
+// starts with only its root hash loads nodes, e.g. through the pre-image oracle.
+pub trait NodeSource {
+	fn get_node(&self, hash: &Hash) -> Option<Vec<u8>>;
+}
+
+impl NodeSource for HashMap<Hash, Vec<u8>> {
+	fn get_node(&self, hash: &Hash) -> Option<Vec<u8>> {
+		self.get(hash).cloned()
+	}
+}
+
+// The empty source is used by tries that are fully held in memory.
+impl NodeSource for () {
+	fn get_node(&self, _: &Hash) -> Option<Vec<u8>> {
+		None
+	}
+}
+
+// A shared source lets several tries, e.g. the storage tries of a state, load from one source.
+impl<T: NodeSource + ?Sized> NodeSource for Rc<T> {
+	fn get_node(&self, hash: &Hash) -> Option<Vec<u8>> {
+		(**self).get_node(hash)
+	}
+}
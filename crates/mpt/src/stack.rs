@@ -0,0 +1,151 @@
+use crate::misc::*;
+use crate::{MptError, EMPTY_ROOT};
+use core::types::{keccak, Hash};
+
+// StackTrie computes the root of a trie from keys that are inserted in increasing order. Once a
+// key is inserted nothing to the left of it can change, so those subtrees are encoded & hashed
+// straight away. Only the branches on the path to the last key are kept in memory, which makes
+// it much cheaper than an MPT when every key is known up front, like the receipts of a block.
+#[derive(Default)]
+pub struct StackTrie {
+	// last is the nibble path & value of the last key. Its leaf can only be encoded once the
+	// next key shows where the two paths split.
+	last: Option<(Vec<u8>, Vec<u8>)>,
+	// branches are the unfinished branch nodes on the path to the last key, from the root down.
+	branches: Vec<StackBranch>,
+}
+
+impl StackTrie {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	// insert adds the key & value to the trie. Keys must be inserted in strictly increasing order.
+	pub fn insert(&mut self, k: Vec<u8>, v: Vec<u8>) -> Result<(), MptError> {
+		let nibbles = bytes_to_nibbles(&k);
+		let Some((last, value)) = self.last.take() else {
+			self.last = Some((nibbles, v));
+			return Ok(());
+		};
+		if nibbles <= last {
+			self.last = Some((last, value));
+			return Err(MptError::UnsortedKey);
+		}
+		// The new key splits off at the branch at depth common, so everything below it is finished.
+		let common = match_paths(&nibbles, &last).0.len();
+		let subtree = self.fold(&last, Subtree::Leaf(value), common + 1);
+		match self.branches.last_mut() {
+			Some(branch) if branch.depth == common => branch.set_child(&last, subtree),
+			_ => {
+				let mut branch = StackBranch::new(common);
+				match subtree {
+					// The last key is a prefix of the new key, so its value is stored in the branch.
+					Subtree::Leaf(value) if last.len() == common => branch.value = Some(value),
+					subtree => branch.set_child(&last, subtree),
+				}
+				self.branches.push(branch);
+			}
+		}
+		self.last = Some((nibbles, v));
+		Ok(())
+	}
+
+	// hash finishes every branch on the path to the last key & returns the root hash.
+	pub fn hash(mut self) -> Hash {
+		let Some((last, value)) = self.last.take() else {
+			return EMPTY_ROOT;
+		};
+		let root = self.fold(&last, Subtree::Leaf(value), 0).rlp_bytes(&last, 0);
+		keccak(root)
+	}
+
+	// fold finishes the branches at or below depth on the path to the last key. The subtree is
+	// added to the deepest branch, which then becomes the subtree of the branch above it.
+	fn fold(&mut self, last: &[u8], mut subtree: Subtree, depth: usize) -> Subtree {
+		while self.branches.last().is_some_and(|branch| branch.depth >= depth) {
+			let mut branch = self.branches.pop().unwrap();
+			branch.set_child(last, subtree);
+			subtree = Subtree::Branch {
+				depth: branch.depth,
+				rlp: branch.rlp_bytes(),
+			};
+		}
+		subtree
+	}
+}
+
+// StackBranch is an unfinished branch node. The children to the left of the last key are finished,
+// so only their references are kept.
+struct StackBranch {
+	// depth is the length of the path to the branch, i.e. the index of the nibble that picks a child.
+	depth: usize,
+	children: Vec<RLPEncodeableWrapper>,
+	value: Option<Vec<u8>>,
+}
+
+impl StackBranch {
+	fn new(depth: usize) -> Self {
+		Self {
+			depth,
+			children: (0..16).map(|_| RLPEncodeableWrapper::EmptyString).collect(),
+			value: None,
+		}
+	}
+
+	// set_child adds the finished subtree on the path to the last key as a child of the branch.
+	fn set_child(&mut self, last: &[u8], subtree: Subtree) {
+		let rlp = subtree.rlp_bytes(last, self.depth + 1);
+		self.children[last[self.depth] as usize] = reference(rlp);
+	}
+
+	fn rlp_bytes(self) -> Vec<u8> {
+		let mut list = self.children;
+		list.push(match self.value {
+			Some(value) => RLPEncodeableWrapper::Bytes(value),
+			None => RLPEncodeableWrapper::EmptyString,
+		});
+		let mut bytes = Vec::new();
+		reth_rlp::encode_list(&list, &mut bytes);
+		bytes
+	}
+}
+
+// Subtree is a finished subtree on the path to the last key that has not been added to its parent.
+// Its path starts wherever the parent is, so it is only encoded once the parent is known.
+enum Subtree {
+	// Leaf is the value of the last key.
+	Leaf(Vec<u8>),
+	// Branch is the encoding of a finished branch at depth.
+	Branch { depth: usize, rlp: Vec<u8> },
+}
+
+impl Subtree {
+	// rlp_bytes encodes the subtree as a node that starts at nibble start of the last key. Leaves
+	// hold the rest of the path & branches below start are behind an extension.
+	fn rlp_bytes(self, last: &[u8], start: usize) -> Vec<u8> {
+		let list = match self {
+			Subtree::Leaf(value) => [
+				RLPEncodeableWrapper::Bytes(nibbles_to_compact(&last[start..], false)),
+				RLPEncodeableWrapper::Bytes(value),
+			],
+			Subtree::Branch { depth, rlp } if depth == start => return rlp,
+			Subtree::Branch { depth, rlp } => [
+				RLPEncodeableWrapper::Bytes(nibbles_to_compact(&last[start..depth], true)),
+				reference(rlp),
+			],
+		};
+		let mut bytes = Vec::new();
+		reth_rlp::encode_list(&list, &mut bytes);
+		bytes
+	}
+}
+
+// reference returns how a parent refers to the node: by hash, or by the node's encoding if it is
+// shorter than 32 bytes.
+fn reference(rlp: Vec<u8>) -> RLPEncodeableWrapper {
+	if rlp.len() < 32 {
+		RLPEncodeableWrapper::Raw(rlp)
+	} else {
+		RLPEncodeableWrapper::Bytes(keccak(&rlp).to_vec())
+	}
+}
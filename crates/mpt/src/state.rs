@@ -0,0 +1,190 @@
+use crate::decode::{decode_bytes, decode_list};
+use crate::misc::{encode_bytes, RLPEncodeableWrapper};
+use crate::{MptError, NodeSource, SecureMPT, EMPTY_ROOT};
+use core::{
+	hash_literal,
+	types::{Address, Hash},
+};
+use reth_primitives::U256;
+use reth_rlp::{Decodable, DecodeError, Encodable};
+use std::{
+	collections::{HashMap, HashSet},
+	rc::Rc,
+};
+
+// EMPTY_CODE_HASH is the code hash of an account without code, i.e. keccak of no bytes.
+pub const EMPTY_CODE_HASH: Hash = hash_literal!("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Account {
+	pub nonce: u64,
+	pub balance: U256,
+	pub storage_root: Hash,
+	pub code_hash: Hash,
+}
+
+impl Default for Account {
+	fn default() -> Self {
+		Self {
+			nonce: 0,
+			balance: U256::ZERO,
+			storage_root: EMPTY_ROOT,
+			code_hash: EMPTY_CODE_HASH,
+		}
+	}
+}
+
+impl Account {
+	// rlp_bytes encodes the account as it is stored in the state trie.
+	pub fn rlp_bytes(&self) -> Vec<u8> {
+		let mut nonce = Vec::new();
+		self.nonce.encode(&mut nonce);
+		let list = [
+			RLPEncodeableWrapper::Raw(nonce),
+			RLPEncodeableWrapper::Raw(encode_u256(self.balance)),
+			RLPEncodeableWrapper::Bytes(self.storage_root.to_vec()),
+			RLPEncodeableWrapper::Bytes(self.code_hash.to_vec()),
+		];
+		let mut out = Vec::new();
+		reth_rlp::encode_list(&list, &mut out);
+		out
+	}
+
+	pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+		let items = decode_list(buf)?;
+		if items.len() != 4 {
+			return Err(DecodeError::Custom("account must have 4 fields"));
+		}
+		Ok(Self {
+			nonce: u64::decode(&mut &items[0][..])?,
+			balance: decode_u256(items[1])?,
+			storage_root: decode_hash(items[2])?,
+			code_hash: decode_hash(items[3])?,
+		})
+	}
+}
+
+// StateDb is the Ethereum world state: a secure trie of accounts where each account has its
+// own secure trie of storage slots. Storage writes are buffered in the storage tries & the
+// storage roots of the accounts are only updated on commit.
+#[derive(Default)]
+pub struct StateDb {
+	accounts: SecureMPT,
+	storage: HashMap<Address, SecureMPT>,
+	dirty: HashSet<Address>,
+	source: Option<Rc<dyn NodeSource>>,
+}
+
+impl StateDb {
+	// with_source creates a state that only knows its root hash. Accounts & storage slots are
+	// loaded from the source as they are needed.
+	pub fn with_source(root: Hash, source: impl NodeSource + 'static) -> Self {
+		let source: Rc<dyn NodeSource> = Rc::new(source);
+		Self {
+			accounts: SecureMPT::with_source(root, source.clone()),
+			storage: HashMap::new(),
+			dirty: HashSet::new(),
+			source: Some(source),
+		}
+	}
+
+	// get_account returns the account at the address. The storage root is the root as of the
+	// last commit.
+	pub fn get_account(&self, address: Address) -> Result<Option<Account>, MptError> {
+		match self.accounts.get(address.to_vec())? {
+			Some(encoded) => Ok(Some(Account::decode(encoded)?)),
+			None => Ok(None),
+		}
+	}
+
+	// set_account writes the account at the address. Pending storage writes are kept unless the
+	// account is given a different storage root, in which case storage is read from that root.
+	pub fn set_account(&mut self, address: Address, account: Account) -> Result<(), MptError> {
+		let storage_root = self.get_account(address)?.map_or(EMPTY_ROOT, |a| a.storage_root);
+		if account.storage_root != storage_root {
+			self.storage.remove(&address);
+			self.dirty.remove(&address);
+		}
+		self.accounts.insert(address.to_vec(), account.rlp_bytes())
+	}
+
+	// remove_account deletes the account at the address along with all of its storage.
+	pub fn remove_account(&mut self, address: Address) -> Result<Option<Account>, MptError> {
+		let Some(encoded) = self.accounts.remove(address.to_vec())? else {
+			return Ok(None);
+		};
+		self.storage.remove(&address);
+		self.dirty.remove(&address);
+		Ok(Some(Account::decode(&encoded)?))
+	}
+
+	// get_storage returns the value of the storage slot. Unset slots are zero.
+	pub fn get_storage(&mut self, address: Address, slot: Hash) -> Result<U256, MptError> {
+		match self.storage_trie(address)?.get(slot.to_vec())? {
+			Some(encoded) => Ok(decode_u256(encoded)?),
+			None => Ok(U256::ZERO),
+		}
+	}
+
+	// set_storage writes the storage slot. Setting a slot to zero removes it. The account is
+	// created on commit if it does not exist.
+	pub fn set_storage(&mut self, address: Address, slot: Hash, value: U256) -> Result<(), MptError> {
+		let trie = self.storage_trie(address)?;
+		if value == U256::ZERO {
+			trie.remove(slot.to_vec())?;
+		} else {
+			trie.insert(slot.to_vec(), encode_u256(value))?;
+		}
+		self.dirty.insert(address);
+		Ok(())
+	}
+
+	// commit writes the storage roots of every account with pending storage writes & returns
+	// the state root. Accounts that fail to be written stay pending.
+	pub fn commit(&mut self) -> Result<Hash, MptError> {
+		while let Some(&address) = self.dirty.iter().next() {
+			let storage_root = self.storage.get_mut(&address).expect("dirty account has a storage trie").hash();
+			let mut account = self.get_account(address)?.unwrap_or_default();
+			account.storage_root = storage_root;
+			self.accounts.insert(address.to_vec(), account.rlp_bytes())?;
+			self.dirty.remove(&address);
+		}
+		Ok(self.accounts.hash())
+	}
+
+	// storage_trie returns the storage trie of the account, opening it from the account's
+	// storage root the first time it is used.
+	fn storage_trie(&mut self, address: Address) -> Result<&mut SecureMPT, MptError> {
+		if !self.storage.contains_key(&address) {
+			let trie = match (self.get_account(address)?, &self.source) {
+				(Some(account), Some(source)) => SecureMPT::with_source(account.storage_root, source.clone()),
+				(Some(account), None) => SecureMPT::with_source(account.storage_root, ()),
+				(None, _) => SecureMPT::default(),
+			};
+			self.storage.insert(address, trie);
+		}
+		Ok(self.storage.get_mut(&address).unwrap())
+	}
+}
+
+// encode_u256 encodes the value as a big endian byte string without leading zeros.
+fn encode_u256(value: U256) -> Vec<u8> {
+	let bytes = value.to_be_bytes::<32>();
+	let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+	encode_bytes(bytes[start..].to_vec())
+}
+
+fn decode_u256(item: &[u8]) -> Result<U256, DecodeError> {
+	let bytes = decode_bytes(item)?;
+	if bytes.first() == Some(&0) {
+		return Err(DecodeError::LeadingZero);
+	}
+	U256::try_from_be_slice(bytes).ok_or(DecodeError::Overflow)
+}
+
+fn decode_hash(item: &[u8]) -> Result<Hash, DecodeError> {
+	let bytes = decode_bytes(item)?;
+	Ok(Hash::new(
+		bytes.try_into().map_err(|_| DecodeError::Custom("hash must be 32 bytes"))?,
+	))
+}
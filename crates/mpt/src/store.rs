@@ -0,0 +1,102 @@
+use crate::NodeSource;
+use core::types::Hash;
+use std::{
+	collections::HashMap,
+	fs::{File, OpenOptions},
+	io::{self, Read, Seek, SeekFrom, Write},
+	path::Path,
+};
+
+// NodeStore is a NodeSource that trie nodes can also be written to. A trie that is opened
+// with a store loads its nodes from it & writes new nodes back when it is persisted.
+pub trait NodeStore: NodeSource {
+	fn put_node(&mut self, hash: Hash, node: &[u8]) -> io::Result<()>;
+
+	// flush makes the nodes that have been put durable.
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+// The in-memory store keeps nodes for as long as the map is alive.
+impl NodeStore for HashMap<Hash, Vec<u8>> {
+	fn put_node(&mut self, hash: Hash, node: &[u8]) -> io::Result<()> {
+		self.entry(hash).or_insert_with(|| node.to_vec());
+		Ok(())
+	}
+}
+
+// FileStore keeps nodes in an append-only log file. Each record is the node's hash, the
+// length of the node as a big endian u32 & the node itself. The index from hash to record
+// is rebuilt when the file is opened. Nodes are never removed from the log.
+pub struct FileStore {
+	file: File,
+	index: HashMap<Hash, (u64, usize)>,
+	len: u64,
+}
+
+const RECORD_HEADER_LEN: usize = 36;
+
+impl FileStore {
+	// open opens or creates the log at path. A partially written record at the end of the log,
+	// e.g. after a crash, is discarded.
+	pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+		let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+		let mut log = Vec::new();
+		file.read_to_end(&mut log)?;
+
+		let mut index = HashMap::new();
+		let mut offset = 0;
+		while let Some(header) = log.get(offset..offset + RECORD_HEADER_LEN) {
+			let hash = Hash::new(header[..32].try_into().unwrap());
+			let len = u32::from_be_bytes(header[32..].try_into().unwrap()) as usize;
+			let start = offset + RECORD_HEADER_LEN;
+			if start + len > log.len() {
+				break;
+			}
+			index.insert(hash, (start as u64, len));
+			offset = start + len;
+		}
+		if offset < log.len() {
+			file.set_len(offset as u64)?;
+		}
+		Ok(Self {
+			file,
+			index,
+			len: offset as u64,
+		})
+	}
+}
+
+impl NodeSource for FileStore {
+	fn get_node(&self, hash: &Hash) -> Option<Vec<u8>> {
+		let (offset, len) = *self.index.get(hash)?;
+		let mut node = vec![0; len];
+		let mut file = &self.file;
+		file.seek(SeekFrom::Start(offset)).ok()?;
+		file.read_exact(&mut node).ok()?;
+		Some(node)
+	}
+}
+
+impl NodeStore for FileStore {
+	fn put_node(&mut self, hash: Hash, node: &[u8]) -> io::Result<()> {
+		if self.index.contains_key(&hash) {
+			return Ok(());
+		}
+		let len = u32::try_from(node.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "trie node is too large"))?;
+		let mut record = Vec::with_capacity(RECORD_HEADER_LEN + node.len());
+		record.extend(hash.to_vec());
+		record.extend(len.to_be_bytes());
+		record.extend(node);
+		self.file.seek(SeekFrom::Start(self.len))?;
+		self.file.write_all(&record)?;
+		self.index.insert(hash, (self.len + RECORD_HEADER_LEN as u64, node.len()));
+		self.len += record.len() as u64;
+		Ok(())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.file.sync_data()
+	}
+}
@@ -1,5 +1,7 @@
+use crate::db::NodeDb;
 use crate::*;
-use core::hash_literal;
+use core::{hash_literal, types::keccak};
+use std::{cell::Cell, rc::Rc};
 
 struct NibblesCompactTestCase {
 	nibbles: Vec<u8>,
@@ -65,7 +67,7 @@ fn test_nibbles_compact_conversions() {
 	for test in tests {
 		let actual_compact = nibbles_to_compact(&test.nibbles, test.extension);
 		assert_eq!(test.compact, actual_compact);
-		let (actual_nibbles, actual_ext) = compact_to_nibbles(&test.compact);
+		let (actual_nibbles, actual_ext) = compact_to_nibbles(&test.compact).unwrap();
 		assert_eq!(test.nibbles, actual_nibbles);
 		assert_eq!(test.extension, actual_ext);
 	}
@@ -84,39 +86,39 @@ fn test_mpt_get() {
 	let mut mpt = MPT::default();
 	let inputs = vec![("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")];
 	for (k, v) in inputs.iter() {
-		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec());
+		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
 	}
 	for (k, v) in inputs {
-		assert_eq!(mpt.get(k.into()), Some(v.as_bytes()));
+		assert_eq!(mpt.get(k.into()).unwrap(), Some(v.as_bytes()));
 	}
-	assert_eq!(mpt.get("".into()), None);
-	assert_eq!(mpt.get("dogf".into()), None);
-	assert_eq!(mpt.get("hors".into()), None);
-	// assert_eq!(mpt.get("horses".into()), None); // TODO: Find this bug with fuzzing.
+	assert_eq!(mpt.get("".into()).unwrap(), None);
+	assert_eq!(mpt.get("dogf".into()).unwrap(), None);
+	assert_eq!(mpt.get("hors".into()).unwrap(), None);
+	// assert_eq!(mpt.get("horses".into()).unwrap(), None); // TODO: Find this bug with fuzzing.
 }
 
 #[test]
 // Found via fuzzing
 fn test_mpt_empty_overwrite() {
 	let mut mpt = MPT::default();
-	mpt.insert(vec![], vec![]);
-	mpt.insert(vec![2], vec![0]);
-	mpt.insert(vec![], vec![]);
+	mpt.insert(vec![], vec![]).unwrap();
+	mpt.insert(vec![2], vec![0]).unwrap();
+	mpt.insert(vec![], vec![]).unwrap();
 
-	assert_eq!(mpt.get(vec![]), Some(vec![].as_slice()));
-	assert_eq!(mpt.get(vec![2]), Some(vec![0].as_slice()));
+	assert_eq!(mpt.get(vec![]).unwrap(), Some(vec![].as_slice()));
+	assert_eq!(mpt.get(vec![2]).unwrap(), Some(vec![0].as_slice()));
 }
 
 #[test]
 // Found via fuzzing
 fn test_mpt_overwrite_value_of_extension_node() {
 	let mut mpt = MPT::default();
-	mpt.insert(vec![0], vec![]);
-	mpt.insert(vec![0], vec![0]);
-	mpt.insert(vec![], vec![]);
+	mpt.insert(vec![0], vec![]).unwrap();
+	mpt.insert(vec![0], vec![0]).unwrap();
+	mpt.insert(vec![], vec![]).unwrap();
 
-	assert_eq!(mpt.get(vec![]), Some(vec![].as_slice()));
-	assert_eq!(mpt.get(vec![0]), Some(vec![0].as_slice()));
+	assert_eq!(mpt.get(vec![]).unwrap(), Some(vec![].as_slice()));
+	assert_eq!(mpt.get(vec![0]).unwrap(), Some(vec![0].as_slice()));
 }
 
 #[test]
@@ -131,10 +133,10 @@ fn test_mpt_overwrite() {
 		("horse", "mare"),
 	];
 	for (k, v) in inputs.iter() {
-		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec());
+		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
 	}
-	assert_eq!(mpt.get("doge".into()), Some("moon".as_bytes()));
-	assert_eq!(mpt.get("horse".into()), Some("mare".as_bytes()));
+	assert_eq!(mpt.get("doge".into()).unwrap(), Some("moon".as_bytes()));
+	assert_eq!(mpt.get("horse".into()).unwrap(), Some("mare".as_bytes()));
 }
 
 #[test]
@@ -142,12 +144,12 @@ fn test_mpt_prefix_get() {
 	let mut mpt = MPT::default();
 	let inputs = vec![("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")];
 	for (k, v) in inputs.iter() {
-		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec());
+		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
 	}
-	assert_eq!(mpt.get("d".into()), None);
-	assert_eq!(mpt.get("dodo".into()), None);
-	assert_eq!(mpt.get("doges".into()), None);
-	assert_eq!(mpt.get("horses".into()), None);
+	assert_eq!(mpt.get("d".into()).unwrap(), None);
+	assert_eq!(mpt.get("dodo".into()).unwrap(), None);
+	assert_eq!(mpt.get("doges".into()).unwrap(), None);
+	assert_eq!(mpt.get("horses".into()).unwrap(), None);
 }
 
 // Test MPT from https://ethereum.org/en/developers/docs/data-structures-and-encoding/patricia-merkle-trie/
@@ -189,23 +191,1172 @@ fn test_mpt_prefix_get() {
 fn test_mpt_hash() {
 	let mut mpt = MPT::default();
 
-	mpt.insert("do".into(), "verb".into());
+	mpt.insert("do".into(), "verb".into()).unwrap();
 	let hash = mpt.hash();
 	let expected_hash: Hash = hash_literal!("014f07ed95e2e028804d915e0dbd4ed451e394e1acfd29e463c11a060b2ddef7");
 	assert_eq!(expected_hash, hash);
 
-	mpt.insert("dog".into(), "puppy".into());
+	mpt.insert("dog".into(), "puppy".into()).unwrap();
 	let hash = mpt.hash();
 	let expected_hash: Hash = hash_literal!("779db3986dd4f38416bfde49750ef7b13c6ecb3e2221620bcad9267e94604d36");
 	assert_eq!(expected_hash, hash);
 
-	mpt.insert("doge".into(), "coin".into());
+	mpt.insert("doge".into(), "coin".into()).unwrap();
 	let hash = mpt.hash();
 	let expected_hash: Hash = hash_literal!("ef7b2fe20f5d2c30c46ad4d83c39811bcbf1721aef2e805c0e107947320888b6");
 	assert_eq!(expected_hash, hash);
 
-	mpt.insert("horse".into(), "stallion".into());
+	mpt.insert("horse".into(), "stallion".into()).unwrap();
 	let hash = mpt.hash();
 	let expected_hash: Hash = hash_literal!("5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84");
 	assert_eq!(expected_hash, hash);
 }
+
+#[test]
+// Values that are 32 bytes or longer are stored in the leaf, not hashed into the db.
+fn test_mpt_hash_long_value() {
+	let mut mpt = MPT::default();
+	mpt.insert("A".into(), vec![b'a'; 50]).unwrap();
+	let expected_hash: Hash = hash_literal!("d23786fb4a010da3ce639d66d5e904a11dbc02746d1ce25029e53290cabf28ab");
+	assert_eq!(expected_hash, mpt.hash());
+}
+
+#[test]
+fn test_mpt_remove() {
+	let mut mpt = MPT::default();
+	let inputs = [("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")];
+	for (k, v) in inputs.iter() {
+		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+	}
+	assert_eq!(mpt.remove("dog".into()).unwrap(), Some("puppy".into()));
+	assert_eq!(mpt.remove("dog".into()).unwrap(), None);
+	assert_eq!(mpt.remove("horses".into()).unwrap(), None);
+	assert_eq!(mpt.get("dog".into()).unwrap(), None);
+	assert_eq!(mpt.get("do".into()).unwrap(), Some("verb".as_bytes()));
+	assert_eq!(mpt.get("doge".into()).unwrap(), Some("coin".as_bytes()));
+	assert_eq!(mpt.get("horse".into()).unwrap(), Some("stallion".as_bytes()));
+}
+
+#[test]
+fn test_mpt_remove_all() {
+	let mut mpt = MPT::default();
+	let inputs = [("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")];
+	for (k, v) in inputs.iter() {
+		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+	}
+	for (k, _) in inputs.iter() {
+		mpt.remove(k.as_bytes().to_vec()).unwrap();
+	}
+	let expected = hash_literal!("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421");
+	assert_eq!(mpt.hash(), expected);
+}
+
+#[test]
+// Removing keys must collapse the trie into the same shape as if they were never inserted.
+fn test_mpt_remove_canonical() {
+	let inputs = [
+		("do", "verb"),
+		("dog", "puppy"),
+		("doge", "coin"),
+		("horse", "stallion"),
+		("dogglesworth", "cat"),
+	];
+	for i in 0..inputs.len() {
+		let mut mpt = MPT::default();
+		let mut expected = MPT::default();
+		for (j, (k, v)) in inputs.iter().enumerate() {
+			mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+			if i != j {
+				expected.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+			}
+		}
+		mpt.remove(inputs[i].0.as_bytes().to_vec()).unwrap();
+		assert_eq!(mpt.hash(), expected.hash(), "removing {}", inputs[i].0);
+	}
+}
+
+// emptyValues from https://github.com/ethereum/tests/blob/develop/TrieTests/trietest.json
+#[test]
+fn test_mpt_remove_hash() {
+	let mut mpt = MPT::default();
+	mpt.insert("do".into(), "verb".into()).unwrap();
+	mpt.insert("ether".into(), "wookiedoo".into()).unwrap();
+	mpt.insert("horse".into(), "stallion".into()).unwrap();
+	mpt.insert("shaman".into(), "horse".into()).unwrap();
+	mpt.insert("doge".into(), "coin".into()).unwrap();
+	mpt.remove("ether".into()).unwrap();
+	mpt.insert("dog".into(), "puppy".into()).unwrap();
+	mpt.remove("shaman".into()).unwrap();
+	let expected_hash: Hash = hash_literal!("5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84");
+	assert_eq!(expected_hash, mpt.hash());
+}
+
+#[test]
+fn test_mpt_prove() {
+	let mut mpt = MPT::default();
+	assert!(mpt.prove("do".into()).unwrap().is_empty());
+
+	let inputs = [("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")];
+	for (k, v) in inputs.iter() {
+		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+	}
+	let root = mpt.hash();
+	// See the layout of this trie above test_mpt_hash
+	let tests = [
+		("horse", 2),
+		("do", 4),
+		("doge", 4),
+		("dogz", 4),
+		("horses", 2),
+		("cat", 2),
+		("zebra", 1),
+	];
+	for (k, len) in tests {
+		let proof = mpt.prove(k.into()).unwrap();
+		assert_eq!(proof.len(), len, "proof length for {k}");
+		assert_eq!(keccak(&proof[0]), root);
+		// Every node after the root must be referenced by hash from the node before it.
+		for pair in proof.windows(2) {
+			let hash = keccak(&pair[1]).to_vec();
+			assert!(pair[0].windows(32).any(|w| w == hash));
+		}
+	}
+}
+
+#[test]
+fn test_verify_proof() {
+	let mut mpt = MPT::default();
+	assert_eq!(verify_proof(mpt.hash(), b"do", &mpt.prove("do".into()).unwrap()), Ok(None));
+
+	let inputs = [("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")];
+	for (k, v) in inputs.iter() {
+		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+	}
+	let root = mpt.hash();
+	for k in ["do", "dog", "doge", "horse", "d", "dogz", "doges", "horses", "cat", "zebra", ""] {
+		let proof = mpt.prove(k.into()).unwrap();
+		let expected = mpt.get(k.into()).unwrap().map(|v| v.to_vec());
+		assert_eq!(verify_proof(root, k.as_bytes(), &proof), Ok(expected), "key {k}");
+	}
+}
+
+#[test]
+// Every node in this trie is shorter than 32 bytes, so the proof is only the root node.
+fn test_verify_proof_inline_nodes() {
+	let mut mpt = MPT::default();
+	mpt.insert(vec![1], vec![1]).unwrap();
+	mpt.insert(vec![2], vec![2]).unwrap();
+	let root = mpt.hash();
+	for k in [vec![1], vec![2], vec![1, 2], vec![3], vec![]] {
+		let proof = mpt.prove(k.clone()).unwrap();
+		assert_eq!(proof.len(), 1);
+		assert_eq!(verify_proof(root, &k, &proof), Ok(mpt.get(k).unwrap().map(|v| v.to_vec())));
+	}
+}
+
+#[test]
+fn test_verify_proof_invalid() {
+	let mut mpt = MPT::default();
+	let inputs = [("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")];
+	for (k, v) in inputs.iter() {
+		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+	}
+	let root = mpt.hash();
+	let proof = mpt.prove("doge".into()).unwrap();
+
+	let mut tampered = proof.clone();
+	tampered[1][5] ^= 1;
+	assert!(matches!(
+		verify_proof(root, b"doge", &tampered),
+		Err(ProofError::HashMismatch { .. })
+	));
+
+	assert!(matches!(verify_proof(root, b"doge", &proof[..2]), Err(ProofError::MissingNode(..))));
+	assert_eq!(verify_proof(root, b"horse", &proof), Err(ProofError::UnusedNodes));
+	assert!(matches!(verify_proof(EMPTY_ROOT, b"doge", &[]), Ok(None)));
+
+	let garbage = vec![0xc2, 0x01, 0x02];
+	assert!(matches!(
+		verify_proof(keccak(&garbage), b"doge", &[garbage]),
+		Err(ProofError::InvalidNode(..))
+	));
+	let bad_path = vec![0xc3, 0x81, 0x40, 0x01];
+	assert!(matches!(
+		verify_proof(keccak(&bad_path), b"doge", &[bad_path]),
+		Err(ProofError::InvalidNode(..))
+	));
+}
+
+// CountingSource counts how many nodes a lazy trie loads.
+struct CountingSource {
+	db: HashMap<Hash, Vec<u8>>,
+	loads: Rc<Cell<usize>>,
+}
+
+impl NodeSource for CountingSource {
+	fn get_node(&self, hash: &Hash) -> Option<Vec<u8>> {
+		self.loads.set(self.loads.get() + 1);
+		self.db.get_node(hash)
+	}
+}
+
+#[test]
+fn test_lazy_mpt() {
+	let mut mpt = MPT::default();
+	let inputs = [("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")];
+	for (k, v) in inputs.iter() {
+		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+	}
+	let root = mpt.hash();
+	let db = mpt.db.nodes.clone();
+
+	let mut lazy = MPT::with_source(root, db.clone());
+	assert_eq!(lazy.hash(), root);
+	for k in ["do", "dog", "doge", "horse", "d", "dogz", "horses", "cat"] {
+		assert_eq!(lazy.get(k.into()).unwrap(), mpt.get(k.into()).unwrap(), "key {k}");
+		assert_eq!(lazy.prove(k.into()).unwrap(), mpt.prove(k.into()).unwrap(), "key {k}");
+	}
+
+	let mut lazy = MPT::with_source(root, db.clone());
+	lazy.insert("dogglesworth".into(), "cat".into()).unwrap();
+	mpt.insert("dogglesworth".into(), "cat".into()).unwrap();
+	assert_eq!(lazy.hash(), mpt.hash());
+
+	let mut lazy = MPT::with_source(root, db.clone());
+	lazy.remove("horse".into()).unwrap();
+	lazy.remove("doge".into()).unwrap();
+	let mut expected = MPT::default();
+	expected.insert("do".into(), "verb".into()).unwrap();
+	expected.insert("dog".into(), "puppy".into()).unwrap();
+	assert_eq!(lazy.hash(), expected.hash());
+}
+
+#[test]
+// A lazy trie must only load the nodes on the path to the key.
+fn test_lazy_mpt_loads_path() {
+	let mut mpt = MPT::default();
+	for i in 0..=255u8 {
+		mpt.insert(vec![i, i], vec![i; 32]).unwrap();
+	}
+	let root = mpt.hash();
+	let loads = Rc::new(Cell::new(0));
+	let source = CountingSource {
+		db: mpt.db.nodes.clone(),
+		loads: loads.clone(),
+	};
+
+	let mut lazy = MPT::with_source(root, source);
+	// root -> branch on the second nibble -> leaf
+	assert_eq!(lazy.get(vec![7, 7]).unwrap(), Some(&[7; 32][..]));
+	assert_eq!(loads.get(), 3);
+	lazy.insert(vec![7, 8], vec![1]).unwrap();
+	mpt.insert(vec![7, 8], vec![1]).unwrap();
+	assert_eq!(lazy.hash(), mpt.hash());
+	assert_eq!(loads.get(), 3);
+	// The root & the branch for the first nibble are already loaded, only the leaf is new.
+	assert_eq!(lazy.get(vec![8, 8]).unwrap(), Some(&[8; 32][..]));
+	assert_eq!(loads.get(), 4);
+}
+
+#[test]
+fn test_decode_node_roundtrip() {
+	let mut mpt = MPT::default();
+	let inputs = [
+		("do", "verb"),
+		("dog", "puppy"),
+		("doge", "coin"),
+		("horse", "stallion"),
+		("dogglesworth", "cat"),
+	];
+	for (k, v) in inputs.iter() {
+		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+	}
+	mpt.hash();
+	for encoded in mpt.db.nodes.values() {
+		let mut node = Node::decode(encoded).unwrap();
+		assert_eq!(&node.rlp_bytes(&mut NodeDb::default()), encoded);
+	}
+}
+
+#[test]
+fn test_mpt_from_db() {
+	let mut mpt = MPT::default();
+	let inputs = [
+		("do", "verb"),
+		("dog", "puppy"),
+		("doge", "coin"),
+		("horse", "stallion"),
+		("A", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"),
+	];
+	for (k, v) in inputs.iter() {
+		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+	}
+	let root = mpt.hash();
+
+	let mut decoded = MPT::from_db(root, mpt.db.nodes.clone()).unwrap();
+	assert_eq!(decoded.hash(), root);
+	for (k, v) in inputs.iter() {
+		assert_eq!(decoded.get(k.as_bytes().to_vec()).unwrap(), Some(v.as_bytes()));
+	}
+	decoded.insert("dogglesworth".into(), "cat".into()).unwrap();
+	mpt.insert("dogglesworth".into(), "cat".into()).unwrap();
+	assert_eq!(decoded.hash(), mpt.hash());
+
+	let mut empty = MPT::from_db(EMPTY_ROOT, HashMap::new()).unwrap();
+	assert_eq!(empty.hash(), EMPTY_ROOT);
+	assert!(MPT::from_db(root, HashMap::new()).is_err());
+}
+
+// dirty_nodes counts the branch & extension nodes that have to be encoded again.
+fn dirty_nodes(node: &Node) -> usize {
+	match node {
+		Node::Branch(node) => node.encoded.is_none() as usize + node.children.iter().map(|c| dirty_nodes(c)).sum::<usize>(),
+		Node::Extension(node) => node.encoded.is_none() as usize + dirty_nodes(&node.child),
+		_ => 0,
+	}
+}
+
+#[test]
+fn test_mpt_hash_cache() {
+	let mut mpt = MPT::default();
+	for i in 0..=255u8 {
+		mpt.insert(vec![i, i], vec![i; 32]).unwrap();
+	}
+	// 17 branch nodes & 256 leaves
+	assert_eq!(dirty_nodes(&mpt.root), 273);
+	mpt.hash();
+	assert_eq!(dirty_nodes(&mpt.root), 0);
+
+	// Only the root, the branch under it & the leaf are on the path to the key.
+	mpt.insert(vec![7, 7], vec![1; 32]).unwrap();
+	assert_eq!(dirty_nodes(&mpt.root), 3);
+	mpt.hash();
+	mpt.remove(vec![8, 8]).unwrap();
+	assert_eq!(dirty_nodes(&mpt.root), 2);
+	mpt.hash();
+	assert_eq!(dirty_nodes(&mpt.root), 0);
+}
+
+#[test]
+// The cached hashes must always match a trie that is built from scratch.
+fn test_mpt_hash_cache_matches_rebuild() {
+	let mut mpt = MPT::default();
+	let mut expected = std::collections::BTreeMap::new();
+	for i in 0..200u32 {
+		let k = (i * 7919 % 97).to_be_bytes()[2..].to_vec();
+		if i % 3 == 0 {
+			mpt.remove(k.clone()).unwrap();
+			expected.remove(&k);
+		} else {
+			mpt.insert(k.clone(), i.to_be_bytes().repeat(i as usize % 10)).unwrap();
+			expected.insert(k, i.to_be_bytes().repeat(i as usize % 10));
+		}
+		let mut rebuilt = MPT::default();
+		for (k, v) in expected.iter() {
+			rebuilt.insert(k.clone(), v.clone()).unwrap();
+		}
+		assert_eq!(mpt.hash(), rebuilt.hash(), "step {i}");
+	}
+}
+
+#[test]
+// Nodes that are no longer part of the trie must be removed from the db.
+fn test_mpt_db_pruned() {
+	let mut mpt = MPT::default();
+	for i in 0..=255u8 {
+		mpt.insert(vec![i, i], vec![i; 32]).unwrap();
+	}
+	mpt.hash();
+	let mut expected = MPT::default();
+	for i in 0..=255u8 {
+		match i % 3 {
+			0 => {
+				mpt.remove(vec![i, i]).unwrap();
+			}
+			1 => {
+				mpt.insert(vec![i, i], vec![i; 40]).unwrap();
+				expected.insert(vec![i, i], vec![i; 40]).unwrap();
+			}
+			_ => expected.insert(vec![i, i], vec![i; 32]).unwrap(),
+		}
+		mpt.insert(vec![i], vec![i; 33]).unwrap();
+		expected.insert(vec![i], vec![i; 33]).unwrap();
+	}
+	assert_eq!(mpt.hash(), expected.hash());
+	let mut keys: Vec<_> = mpt.db.nodes.keys().collect();
+	let mut expected_keys: Vec<_> = expected.db.nodes.keys().collect();
+	keys.sort_by_key(|h| h.to_vec());
+	expected_keys.sort_by_key(|h| h.to_vec());
+	assert_eq!(keys, expected_keys);
+}
+
+#[test]
+// The root & the nodes of a trie loaded from a db are pruned like the nodes the trie wrote.
+fn test_mpt_db_pruned_root_and_from_db() {
+	// A root shorter than 32 bytes is stored by hash.
+	let mut mpt = MPT::default();
+	mpt.insert(vec![1], vec![1]).unwrap();
+	let root = mpt.hash();
+	assert_eq!(mpt.db.nodes.keys().collect::<Vec<_>>(), [&root]);
+	mpt.insert(vec![1], vec![2]).unwrap();
+	let root = mpt.hash();
+	assert_eq!(mpt.db.nodes.keys().collect::<Vec<_>>(), [&root]);
+	mpt.remove(vec![1]).unwrap();
+	assert_eq!(mpt.hash(), EMPTY_ROOT);
+	assert_eq!(mpt.db.nodes.keys().collect::<Vec<_>>(), [&EMPTY_ROOT]);
+
+	let mut mpt = MPT::default();
+	let mut expected = MPT::default();
+	for i in 0..=255u8 {
+		mpt.insert(vec![i, i], vec![i; 32]).unwrap();
+		expected.insert(vec![i, i], vec![!i; 32]).unwrap();
+	}
+	let root = mpt.hash();
+	expected.hash();
+	// Nodes that are not part of the trie are dropped.
+	let mut db = mpt.db.nodes.clone();
+	db.extend(expected.db.nodes.clone());
+	let mut decoded = MPT::from_db(root, db).unwrap();
+	for i in 0..=255u8 {
+		decoded.insert(vec![i, i], vec![!i; 32]).unwrap();
+	}
+	assert_eq!(decoded.hash(), expected.hash());
+	let mut keys: Vec<_> = decoded.db.nodes.keys().collect();
+	let mut expected_keys: Vec<_> = expected.db.nodes.keys().collect();
+	keys.sort_by_key(|h| h.to_vec());
+	expected_keys.sort_by_key(|h| h.to_vec());
+	assert_eq!(keys, expected_keys);
+}
+
+#[test]
+fn test_ordered_trie_key() {
+	assert_eq!(ordered_trie_key(0), vec![0x80]);
+	assert_eq!(ordered_trie_key(1), vec![0x01]);
+	assert_eq!(ordered_trie_key(127), vec![0x7f]);
+	assert_eq!(ordered_trie_key(128), vec![0x81, 0x80]);
+	assert_eq!(ordered_trie_key(256), vec![0x82, 0x01, 0x00]);
+}
+
+#[test]
+fn test_ordered_trie_root() {
+	let (root, db) = ordered_trie_root(std::iter::empty());
+	assert_eq!(root, EMPTY_ROOT);
+	assert_eq!(db.get(&EMPTY_ROOT), Some(&vec![0x80]));
+
+	let items: Vec<Vec<u8>> = (0..300u32).map(|i| i.to_be_bytes().repeat(i as usize % 20 + 1)).collect();
+	let (root, db) = ordered_trie_root(items.clone().into_iter());
+	let mut mpt = MPT::default();
+	for (i, item) in items.iter().enumerate().rev() {
+		mpt.insert(ordered_trie_key(i), item.clone()).unwrap();
+	}
+	assert_eq!(root, mpt.hash());
+
+	// The pre-images are enough to read every item back from the root.
+	let mpt = MPT::from_db(root, db).unwrap();
+	for (i, item) in items.iter().enumerate() {
+		assert_eq!(mpt.get(ordered_trie_key(i)).unwrap(), Some(&item[..]));
+	}
+}
+
+#[test]
+fn test_stack_trie() {
+	assert_eq!(StackTrie::new().hash(), EMPTY_ROOT);
+
+	let mut entries = std::collections::BTreeMap::new();
+	for i in 0..500u32 {
+		let hash = keccak(i.to_be_bytes()).to_vec();
+		// Short keys share prefixes & some keys are prefixes of others.
+		entries.insert(hash[..(i as usize % 7) + 1].to_vec(), i.to_be_bytes().repeat(i as usize % 12));
+	}
+	entries.insert(vec![], b"root".to_vec());
+	let mut mpt = MPT::default();
+	let mut stack = StackTrie::new();
+	for (k, v) in entries.iter() {
+		mpt.insert(k.clone(), v.clone()).unwrap();
+		stack.insert(k.clone(), v.clone()).unwrap();
+	}
+	assert_eq!(stack.hash(), mpt.hash());
+
+	let mut stack = StackTrie::new();
+	stack.insert(b"dog".to_vec(), b"puppy".to_vec()).unwrap();
+	assert_eq!(stack.insert(b"do".to_vec(), b"verb".to_vec()), Err(MptError::UnsortedKey));
+	assert_eq!(stack.insert(b"dog".to_vec(), b"puppy".to_vec()), Err(MptError::UnsortedKey));
+	stack.insert(b"doge".to_vec(), b"coin".to_vec()).unwrap();
+	let mut mpt = MPT::default();
+	mpt.insert(b"dog".to_vec(), b"puppy".to_vec()).unwrap();
+	mpt.insert(b"doge".to_vec(), b"coin".to_vec()).unwrap();
+	assert_eq!(stack.hash(), mpt.hash());
+
+	for n in [0, 1, 2, 127, 128, 129, 300] {
+		let items: Vec<Vec<u8>> = (0..n as u32).map(|i| i.to_be_bytes().repeat(i as usize % 20 + 1)).collect();
+		assert_eq!(
+			ordered_trie_hash(items.clone().into_iter()),
+			ordered_trie_root(items.into_iter()).0,
+			"{n} items"
+		);
+	}
+}
+
+#[test]
+fn test_secure_mpt() {
+	let mut secure = SecureMPT::with_preimages();
+	let mut mpt = MPT::default();
+	let inputs = [("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")];
+	for (k, v) in inputs.iter() {
+		secure.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+		mpt.insert(keccak(k).to_vec(), v.as_bytes().to_vec()).unwrap();
+	}
+	let root = secure.hash();
+	assert_eq!(root, mpt.hash());
+	for (k, v) in inputs.iter() {
+		assert_eq!(secure.get(k.as_bytes().to_vec()).unwrap(), Some(v.as_bytes()));
+		assert_eq!(secure.preimage(&keccak(k)), Some(k.as_bytes()));
+		let proof = secure.prove(k.as_bytes().to_vec()).unwrap();
+		assert_eq!(verify_proof(root, &keccak(k).to_vec(), &proof), Ok(Some(v.as_bytes().to_vec())));
+	}
+	assert_eq!(secure.get("cat".into()).unwrap(), None);
+	assert_eq!(secure.preimage(&keccak("cat")), None);
+
+	assert_eq!(secure.remove("dog".into()).unwrap(), Some("puppy".into()));
+	mpt.remove(keccak("dog").to_vec()).unwrap();
+	assert_eq!(secure.hash(), mpt.hash());
+
+	let secure = SecureMPT::with_source(mpt.hash(), mpt.db.nodes.clone());
+	assert_eq!(secure.get("doge".into()).unwrap(), Some("coin".as_bytes()));
+	assert_eq!(secure.preimage(&keccak("doge")), None);
+}
+
+#[test]
+fn test_account_rlp() {
+	let account = Account::default();
+	let expected = hex::decode("f8448080a056e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421a0c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470").unwrap();
+	assert_eq!(account.rlp_bytes(), expected);
+	assert_eq!(EMPTY_CODE_HASH, keccak([]));
+
+	let account = Account {
+		nonce: 7,
+		balance: reth_primitives::U256::from(1_000_000_000_000_000_000u128),
+		..Default::default()
+	};
+	assert_eq!(Account::decode(&account.rlp_bytes()), Ok(account));
+	assert!(Account::decode(&hex::decode("c3808080").unwrap()).is_err());
+}
+
+#[test]
+fn test_state_db() {
+	use core::{address_literal, types::Address};
+	use reth_primitives::U256;
+
+	let a = address_literal!("1000000000000000000000000000000000000001");
+	let b = address_literal!("2000000000000000000000000000000000000002");
+	let (x, y) = (Hash::new([1; 32]), Hash::new([2; 32]));
+
+	let mut state = StateDb::default();
+	state.set_account(
+		a,
+		Account {
+			nonce: 1,
+			balance: U256::from(100),
+			..Default::default()
+		},
+	)
+	.unwrap();
+	state.set_storage(a, x, U256::from(5)).unwrap();
+	state.set_storage(a, y, U256::from(0x1234)).unwrap();
+	state.set_storage(b, x, U256::from(7)).unwrap();
+	assert_eq!(state.get_account(a).unwrap().unwrap().storage_root, EMPTY_ROOT);
+	let root = state.commit().unwrap();
+
+	// Build the same state out of plain tries keyed by hash.
+	let mut storage_a = MPT::default();
+	storage_a.insert(keccak(x.to_vec()).to_vec(), vec![0x05]).unwrap();
+	storage_a.insert(keccak(y.to_vec()).to_vec(), vec![0x82, 0x12, 0x34]).unwrap();
+	let mut storage_b = MPT::default();
+	storage_b.insert(keccak(x.to_vec()).to_vec(), vec![0x07]).unwrap();
+	let account_a = Account {
+		nonce: 1,
+		balance: U256::from(100),
+		storage_root: storage_a.hash(),
+		..Default::default()
+	};
+	let account_b = Account {
+		storage_root: storage_b.hash(),
+		..Default::default()
+	};
+	let mut accounts = MPT::default();
+	accounts.insert(keccak(a.to_vec()).to_vec(), account_a.rlp_bytes()).unwrap();
+	accounts.insert(keccak(b.to_vec()).to_vec(), account_b.rlp_bytes()).unwrap();
+	assert_eq!(root, accounts.hash());
+
+	assert_eq!(state.get_account(a).unwrap(), Some(account_a));
+	assert_eq!(state.get_account(b).unwrap(), Some(account_b));
+	assert_eq!(state.get_storage(a, y).unwrap(), U256::from(0x1234));
+	assert_eq!(state.get_storage(b, y).unwrap(), U256::ZERO);
+	assert_eq!(state.get_account(Address::default()).unwrap(), None);
+
+	// Read & write the state lazily from the nodes of the tries.
+	let mut nodes = accounts.db.nodes.clone();
+	nodes.extend(storage_a.db.nodes.clone());
+	nodes.extend(storage_b.db.nodes.clone());
+	let mut lazy = StateDb::with_source(root, nodes);
+	assert_eq!(lazy.get_account(a).unwrap(), Some(account_a));
+	assert_eq!(lazy.get_storage(a, x).unwrap(), U256::from(5));
+	assert_eq!(lazy.get_storage(b, x).unwrap(), U256::from(7));
+
+	for state in [&mut state, &mut lazy] {
+		state.set_storage(a, y, U256::ZERO).unwrap();
+		state.set_account(
+			a,
+			Account {
+				nonce: 2,
+				..state.get_account(a).unwrap().unwrap()
+			},
+		)
+		.unwrap();
+	}
+	storage_a.remove(keccak(y.to_vec()).to_vec()).unwrap();
+	let account_a = Account {
+		nonce: 2,
+		storage_root: storage_a.hash(),
+		..account_a
+	};
+	accounts.insert(keccak(a.to_vec()).to_vec(), account_a.rlp_bytes()).unwrap();
+	assert_eq!(state.commit().unwrap(), accounts.hash());
+	assert_eq!(lazy.commit().unwrap(), accounts.hash());
+
+	assert_eq!(state.remove_account(b).unwrap(), Some(account_b));
+	assert_eq!(state.get_storage(b, x).unwrap(), U256::ZERO);
+	accounts.remove(keccak(b.to_vec()).to_vec()).unwrap();
+	assert_eq!(state.commit().unwrap(), accounts.hash());
+}
+
+#[test]
+fn test_iter() {
+	use std::collections::BTreeMap;
+
+	let mut mpt = MPT::default();
+	assert_eq!(mpt.iter().next(), None);
+
+	let mut map = BTreeMap::new();
+	let keys: [&[u8]; 9] = [b"", b"do", b"dog", b"doge", b"horse", b"a", b"\x00", b"\xff\x01", &[0x61; 40]];
+	for (i, k) in keys.iter().enumerate() {
+		mpt.insert(k.to_vec(), vec![i as u8; i + 1]).unwrap();
+		map.insert(k.to_vec(), vec![i as u8; i + 1]);
+	}
+	for i in 0..200u32 {
+		let k = keccak(i.to_be_bytes()).to_vec()[..(i as usize % 5) + 1].to_vec();
+		mpt.insert(k.clone(), i.to_be_bytes().to_vec()).unwrap();
+		map.insert(k, i.to_be_bytes().to_vec());
+	}
+	let expected: Vec<_> = map.iter().map(|(k, v)| (k.clone(), &v[..])).collect();
+	assert_eq!(mpt.iter().map(Result::unwrap).collect::<Vec<_>>(), expected);
+
+	// Iterating a lazily loaded trie loads every node.
+	let root = mpt.hash();
+	let lazy = MPT::with_source(root, mpt.db.nodes.clone());
+	assert_eq!(lazy.iter().map(Result::unwrap).collect::<Vec<_>>(), expected);
+
+	let range = |start: &[u8], end: &[u8]| {
+		map.range(start.to_vec()..end.to_vec())
+			.map(|(k, v)| (k.clone(), &v[..]))
+			.collect::<Vec<_>>()
+	};
+	assert_eq!(
+		mpt.range(b"do".to_vec()..b"e".to_vec()).map(Result::unwrap).collect::<Vec<_>>(),
+		range(b"do", b"e")
+	);
+	assert_eq!(
+		mpt.range(b"dog".to_vec()..b"horse".to_vec())
+			.map(Result::unwrap)
+			.collect::<Vec<_>>(),
+		range(b"dog", b"horse")
+	);
+	assert_eq!(
+		lazy.range(b"\x10".to_vec()..b"\xa0\x01".to_vec())
+			.map(Result::unwrap)
+			.collect::<Vec<_>>(),
+		range(b"\x10", b"\xa0\x01")
+	);
+	assert_eq!(mpt.range(b"\x80".to_vec()..b"\x80".to_vec()).count(), 0);
+
+	let keys = |iter: &mut dyn Iterator<Item = (Vec<u8>, &[u8])>| iter.map(|(k, _)| k).collect::<Vec<_>>();
+	assert_eq!(
+		keys(&mut mpt.range(..b"a".to_vec()).map(Result::unwrap)),
+		keys(&mut mpt.iter().map(Result::unwrap).take_while(|(k, _)| k < &b"a".to_vec()))
+	);
+	assert_eq!(
+		keys(&mut mpt.range(b"dog".to_vec()..=b"doge".to_vec()).map(Result::unwrap)),
+		[b"dog".to_vec(), b"doge".to_vec()]
+	);
+	let after_dog = (
+		std::ops::Bound::Excluded(b"dog".to_vec()),
+		std::ops::Bound::Included(b"horse".to_vec()),
+	);
+	assert_eq!(
+		keys(&mut mpt.range(after_dog).map(Result::unwrap)),
+		keys(&mut mpt
+			.iter()
+			.map(Result::unwrap)
+			.filter(|(k, _)| k > &b"dog".to_vec() && k <= &b"horse".to_vec()))
+	);
+	assert_eq!(mpt.range::<Vec<u8>>(..).count(), map.len());
+}
+
+#[test]
+fn test_multiproof() {
+	let mut mpt = MPT::default();
+	for i in 0..300 {
+		mpt.insert(ordered_trie_key(i), keccak(i.to_be_bytes()).to_vec()).unwrap();
+	}
+	let root = mpt.hash();
+
+	let keys: Vec<_> = [0, 1, 17, 128, 129, 299, 300, 1000].into_iter().map(ordered_trie_key).collect();
+	let proof = mpt.prove_multi(&keys).unwrap();
+	let expected: Vec<_> = keys.iter().map(|k| mpt.get(k.clone()).unwrap().map(|v| v.to_vec())).collect();
+	assert_eq!(verify_multiproof(root, &keys, &proof), Ok(expected));
+
+	// Shared nodes, e.g. the root, are only included once.
+	let single: usize = keys.iter().map(|k| mpt.prove(k.clone()).unwrap().len()).sum();
+	assert!(proof.len() < single);
+
+	assert!(matches!(
+		verify_multiproof(root, &keys, &proof[1..]),
+		Err(ProofError::MissingNode(..))
+	));
+	assert_eq!(verify_multiproof(root, &keys[..2], &proof), Err(ProofError::UnusedNodes));
+	assert_eq!(verify_multiproof(EMPTY_ROOT, &keys, &[]), Ok(vec![None; keys.len()]));
+}
+
+#[test]
+fn test_range_proof() {
+	let mut mpt = MPT::default();
+	for i in 0..300 {
+		mpt.insert(ordered_trie_key(i), keccak(i.to_be_bytes()).to_vec()).unwrap();
+	}
+	for k in ["do", "dog", "doge", "horse"] {
+		mpt.insert(k.into(), k.into()).unwrap();
+	}
+	let root = mpt.hash();
+
+	let ranges: [(&[u8], &[u8]); 7] = [
+		(b"", b"\xff"),
+		(&[0x81, 0x90], &[0x81, 0xd0]),
+		(&[0x82, 0x01, 0x00], &[0x82, 0x01, 0x0a]),
+		(b"dog", b"horse"),
+		(b"do", b"dog\x00"),
+		(b"cat", b"cow"),
+		(b"z", b"a"),
+	];
+	for (start, end) in ranges {
+		let proof = mpt.prove_range(start, end).unwrap();
+		let expected: Vec<_> = mpt.range(start..end).map(Result::unwrap).map(|(k, v)| (k, v.to_vec())).collect();
+		assert_eq!(expected.is_empty(), start >= end || start == b"cat");
+		assert_eq!(
+			verify_range_proof(root, start, end, &proof),
+			Ok(expected),
+			"range {start:x?}..{end:x?}"
+		);
+	}
+
+	// Leaving a node out of the proof could hide keys in the range, so it must be rejected.
+	let proof = mpt.prove_range(&[0x81, 0x90], &[0x81, 0xd0]).unwrap();
+	for i in 0..proof.len() {
+		let mut partial = proof.clone();
+		partial.remove(i);
+		assert!(matches!(
+			verify_range_proof(root, &[0x81, 0x90], &[0x81, 0xd0], &partial),
+			Err(ProofError::MissingNode(..))
+		));
+	}
+	let mut extra = proof.clone();
+	extra.push(mpt.prove("horse".into()).unwrap().pop().unwrap());
+	assert_eq!(
+		verify_range_proof(root, &[0x81, 0x90], &[0x81, 0xd0], &extra),
+		Err(ProofError::UnusedNodes)
+	);
+	assert_eq!(verify_range_proof(EMPTY_ROOT, b"", b"\xff", &[]), Ok(vec![]));
+}
+
+#[test]
+fn test_file_store() {
+	let path = std::env::temp_dir().join(format!("mpt-test-file-store-{}", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+
+	let mut expected = MPT::default();
+	let mut mpt = MPT::with_store(EMPTY_ROOT, FileStore::open(&path).unwrap());
+	for i in 0..100u32 {
+		mpt.insert(keccak(i.to_be_bytes()).to_vec(), i.to_be_bytes().repeat(i as usize % 10 + 1))
+			.unwrap();
+		expected.insert(keccak(i.to_be_bytes()).to_vec(), i.to_be_bytes().repeat(i as usize % 10 + 1))
+			.unwrap();
+	}
+	let root = mpt.persist().unwrap();
+	assert_eq!(root, expected.hash());
+	drop(mpt);
+
+	// Reopen the trie, change it & persist the new nodes next to the old ones.
+	let mut mpt = MPT::with_store(root, FileStore::open(&path).unwrap());
+	assert_eq!(
+		mpt.get(keccak(7u32.to_be_bytes()).to_vec()).unwrap(),
+		expected.get(keccak(7u32.to_be_bytes()).to_vec()).unwrap()
+	);
+	mpt.remove(keccak(7u32.to_be_bytes()).to_vec()).unwrap();
+	mpt.insert(b"dog".to_vec(), b"puppy".to_vec()).unwrap();
+	expected.remove(keccak(7u32.to_be_bytes()).to_vec()).unwrap();
+	expected.insert(b"dog".to_vec(), b"puppy".to_vec()).unwrap();
+	let new_root = mpt.persist().unwrap();
+	assert_eq!(new_root, expected.hash());
+	drop(mpt);
+
+	// A torn write at the end of the log is discarded.
+	let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+	std::io::Write::write_all(&mut file, &[0xab; 40]).unwrap();
+	drop(file);
+
+	let store = FileStore::open(&path).unwrap();
+	for (hash, node) in expected.db.nodes.iter() {
+		assert_eq!(store.get_node(hash).as_ref(), Some(node));
+	}
+	let old = MPT::with_store(root, store);
+	assert_eq!(old.iter().count(), 100);
+	let mpt = MPT::with_store(new_root, FileStore::open(&path).unwrap());
+	assert_eq!(
+		mpt.iter().map(Result::unwrap).collect::<Vec<_>>(),
+		expected.iter().map(Result::unwrap).collect::<Vec<_>>()
+	);
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_memory_store() {
+	let mut store = HashMap::new();
+	store.put_node(keccak([1]), &[1]).unwrap();
+	store.put_node(keccak([1]), &[2]).unwrap();
+	assert_eq!(store.get_node(&keccak([1])), Some(vec![1]));
+}
+
+#[test]
+fn test_checkpoint() {
+	let mut mpt = MPT::default();
+	mpt.insert(b"do".to_vec(), b"verb".to_vec()).unwrap();
+	mpt.insert(b"dog".to_vec(), b"puppy".to_vec()).unwrap();
+	let root = mpt.hash();
+
+	let outer = mpt.checkpoint();
+	mpt.insert(b"doge".to_vec(), b"coin".to_vec()).unwrap();
+	mpt.insert(b"dog".to_vec(), b"hound".to_vec()).unwrap();
+	let middle_root = mpt.hash();
+
+	let inner = mpt.checkpoint();
+	mpt.remove(b"do".to_vec()).unwrap();
+	mpt.remove(b"cat".to_vec()).unwrap();
+	mpt.insert(b"horse".to_vec(), b"stallion".to_vec()).unwrap();
+	mpt.revert_to(inner).unwrap();
+	assert_eq!(mpt.hash(), middle_root);
+	assert_eq!(mpt.get(b"do".to_vec()).unwrap(), Some(&b"verb"[..]));
+	assert_eq!(mpt.get(b"horse".to_vec()).unwrap(), None);
+
+	mpt.revert_to(outer).unwrap();
+	assert_eq!(mpt.hash(), root);
+	assert_eq!(mpt.get(b"dog".to_vec()).unwrap(), Some(&b"puppy"[..]));
+	assert_eq!(mpt.get(b"doge".to_vec()).unwrap(), None);
+}
+
+#[test]
+fn test_checkpoint_committed() {
+	let mut mpt = MPT::default();
+	let checkpoint = mpt.checkpoint();
+	mpt.insert(b"horse".to_vec(), b"stallion".to_vec()).unwrap();
+	mpt.commit();
+	let root = mpt.hash();
+	assert_eq!(mpt.revert_to(checkpoint), Err(MptError::InvalidCheckpoint));
+	assert_eq!(mpt.hash(), root);
+}
+
+#[test]
+fn test_checkpoint_reverted() {
+	let mut mpt = MPT::default();
+	let outer = mpt.checkpoint();
+	mpt.insert(b"do".to_vec(), b"verb".to_vec()).unwrap();
+	let inner = mpt.checkpoint();
+	mpt.insert(b"dog".to_vec(), b"puppy".to_vec()).unwrap();
+	mpt.revert_to(outer).unwrap();
+
+	// inner was consumed by reverting to outer.
+	mpt.insert(b"horse".to_vec(), b"stallion".to_vec()).unwrap();
+	let root = mpt.hash();
+	assert_eq!(mpt.revert_to(inner), Err(MptError::InvalidCheckpoint));
+	assert_eq!(mpt.hash(), root);
+	mpt.revert_to(outer).unwrap();
+	assert_eq!(mpt.hash(), EMPTY_ROOT);
+}
+
+#[test]
+fn test_checkpoint_lazy() {
+	let mut mpt = MPT::default();
+	for i in 0..50u32 {
+		mpt.insert(keccak(i.to_be_bytes()).to_vec(), i.to_be_bytes().to_vec()).unwrap();
+	}
+	let root = mpt.hash();
+
+	let mut lazy = MPT::with_source(root, mpt.db.nodes.clone());
+	let checkpoint = lazy.checkpoint();
+	for i in 25..75u32 {
+		lazy.remove(keccak(i.to_be_bytes()).to_vec()).unwrap();
+		lazy.insert(keccak((i * 2).to_be_bytes()).to_vec(), vec![0xff; 40]).unwrap();
+	}
+	assert_ne!(lazy.hash(), root);
+	lazy.revert_to(checkpoint).unwrap();
+	assert_eq!(lazy.hash(), root);
+}
+
+#[test]
+fn test_checkpoint_missing_node() {
+	// The root is an extension to a branch that is only known by its hash.
+	let mut mpt = MPT::default();
+	mpt.insert(vec![0x10], vec![1; 40]).unwrap();
+	mpt.insert(vec![0x11], vec![2; 40]).unwrap();
+	let root = mpt.hash();
+	let branch = keccak(&mpt.prove(vec![0x10]).unwrap()[1]);
+	let db = HashMap::from([(root, mpt.db.nodes[&root].clone())]);
+
+	// The insert splits the extension without loading the branch, but undoing it collapses the
+	// new root branch into the old branch, which is missing.
+	let mut lazy = MPT::with_source(root, db);
+	let checkpoint = lazy.checkpoint();
+	lazy.insert(vec![0x20], vec![3]).unwrap();
+	let inserted = lazy.hash();
+	assert_eq!(lazy.revert_to(checkpoint), Err(MptError::MissingNode(branch)));
+	assert_eq!(lazy.hash(), inserted);
+
+	// The change is still in the journal & can be undone once the node is available.
+	lazy.db.nodes.insert(branch, mpt.db.nodes[&branch].clone());
+	lazy.revert_to(checkpoint).unwrap();
+	assert_eq!(lazy.hash(), root);
+}
+
+#[test]
+fn test_diff() {
+	let mut old = MPT::default();
+	for i in 0..200u32 {
+		old.insert(keccak(i.to_be_bytes()).to_vec(), i.to_be_bytes().to_vec()).unwrap();
+	}
+	old.insert(b"do".to_vec(), b"verb".to_vec()).unwrap();
+	old.insert(b"dog".to_vec(), b"puppy".to_vec()).unwrap();
+	let old_root = old.hash();
+
+	let mut new = MPT::with_source(old_root, old.db.nodes.clone());
+	new.remove(keccak(3u32.to_be_bytes()).to_vec()).unwrap();
+	new.insert(keccak(5u32.to_be_bytes()).to_vec(), vec![0xff; 40]).unwrap();
+	new.insert(keccak(500u32.to_be_bytes()).to_vec(), vec![1]).unwrap();
+	new.insert(b"doge".to_vec(), b"coin".to_vec()).unwrap();
+	new.remove(b"dog".to_vec()).unwrap();
+	let new_root = new.hash();
+
+	let mut expected = vec![
+		Change::Removed {
+			key: keccak(3u32.to_be_bytes()).to_vec(),
+			value: 3u32.to_be_bytes().to_vec(),
+		},
+		Change::Changed {
+			key: keccak(5u32.to_be_bytes()).to_vec(),
+			old: 5u32.to_be_bytes().to_vec(),
+			new: vec![0xff; 40],
+		},
+		Change::Added {
+			key: keccak(500u32.to_be_bytes()).to_vec(),
+			value: vec![1],
+		},
+		Change::Removed {
+			key: b"dog".to_vec(),
+			value: b"puppy".to_vec(),
+		},
+		Change::Added {
+			key: b"doge".to_vec(),
+			value: b"coin".to_vec(),
+		},
+	];
+	let key = |c: &Change| match c {
+		Change::Added { key, .. } | Change::Removed { key, .. } | Change::Changed { key, .. } => key.clone(),
+	};
+	expected.sort_by_key(key);
+	assert_eq!(old.diff(&mut new).unwrap(), expected);
+	assert_eq!(old.diff(&mut MPT::with_source(old_root, old.db.nodes.clone())).unwrap(), []);
+
+	// Only the nodes on the paths to the changed keys are loaded.
+	let mut db = old.db.nodes.clone();
+	db.extend(new.db.nodes.clone());
+	let loads = Rc::new(Cell::new(0));
+	let source = CountingSource { db, loads: loads.clone() };
+	assert_eq!(diff_roots(old_root, new_root, &source), Ok(expected));
+	assert!(loads.get() < 30, "loaded {} nodes", loads.get());
+
+	let removed = diff_roots(old_root, EMPTY_ROOT, &source).unwrap();
+	assert_eq!(removed.len(), 202);
+	assert!(removed.iter().all(|c| matches!(c, Change::Removed { .. })));
+	assert!(matches!(diff_roots(old_root, keccak([1]), &source), Err(..)));
+}
+
+// fixture_bytes decodes a key or value from the ethereum/tests trie fixtures.
+fn fixture_bytes(s: &str) -> Vec<u8> {
+	match s.strip_prefix("0x") {
+		Some(hex) => hex::decode(hex).unwrap(),
+		None => s.as_bytes().to_vec(),
+	}
+}
+
+// run_trie_fixtures applies the updates of every test in the fixture file to a fresh trie & checks
+// the root. Tests keyed by name map keys to values & are also applied in reverse order.
+fn run_trie_fixtures(fixtures: &str, secure: bool) {
+	let fixtures: serde_json::Map<String, serde_json::Value> = serde_json::from_str(fixtures).unwrap();
+	for (name, test) in fixtures {
+		let updates: Vec<(Vec<u8>, Option<Vec<u8>>)> = match &test["in"] {
+			serde_json::Value::Object(map) => {
+				map.iter().map(|(k, v)| (fixture_bytes(k), v.as_str().map(fixture_bytes))).collect()
+			}
+			serde_json::Value::Array(list) => list
+				.iter()
+				.map(|kv| (fixture_bytes(kv[0].as_str().unwrap()), kv[1].as_str().map(fixture_bytes)))
+				.collect(),
+			_ => panic!("invalid fixture {name}"),
+		};
+		let expected = Hash::new(fixture_bytes(test["root"].as_str().unwrap()).try_into().unwrap());
+
+		let mut orders = vec![updates.clone()];
+		if test["in"].is_object() {
+			orders.push(updates.into_iter().rev().collect());
+		}
+		for updates in orders {
+			let mut mpt = MPT::default();
+			let mut secure_mpt = SecureMPT::default();
+			let mut contents = std::collections::BTreeMap::new();
+			for (k, v) in updates {
+				let key = if secure { keccak(&k).to_vec() } else { k.clone() };
+				match &v {
+					Some(v) => contents.insert(key, v.clone()),
+					None => contents.remove(&key),
+				};
+				match (v, secure) {
+					(Some(v), false) => mpt.insert(k, v).unwrap(),
+					(Some(v), true) => secure_mpt.insert(k, v).unwrap(),
+					(None, false) => drop(mpt.remove(k).unwrap()),
+					(None, true) => drop(secure_mpt.remove(k).unwrap()),
+				}
+			}
+			let root = if secure { secure_mpt.hash() } else { mpt.hash() };
+			assert_eq!(root, expected, "{name}");
+
+			// A stack trie built from the sorted contents has the same root.
+			let mut stack = StackTrie::new();
+			for (k, v) in contents {
+				stack.insert(k, v).unwrap();
+			}
+			assert_eq!(stack.hash(), expected, "{name}");
+		}
+	}
+}
+
+#[test]
+fn test_trie_fixtures() {
+	run_trie_fixtures(include_str!("../testdata/TrieTests/trieanyorder.json"), false);
+	run_trie_fixtures(include_str!("../testdata/TrieTests/trietest.json"), false);
+	run_trie_fixtures(include_str!("../testdata/TrieTests/trieanyorder_secureTrie.json"), true);
+	run_trie_fixtures(include_str!("../testdata/TrieTests/trietest_secureTrie.json"), true);
+	run_trie_fixtures(include_str!("../testdata/TrieTests/hex_encoded_securetrie_test.json"), true);
+}
+
+#[test]
+fn test_mpt_errors() {
+	assert_eq!(compact_to_nibbles(&[]), Err(MptError::InvalidPath));
+	assert_eq!(compact_to_nibbles(&[0x40, 0x12]), Err(MptError::InvalidPath));
+
+	// A missing node is an error & leaves the trie unchanged.
+	let mut mpt = MPT::default();
+	mpt.insert(vec![0x10], vec![1; 40]).unwrap();
+	mpt.insert(vec![0x20], vec![2; 40]).unwrap();
+	let root = mpt.hash();
+	let sibling = keccak(&mpt.prove(vec![0x20]).unwrap()[1]);
+	let mut db = mpt.db.nodes.clone();
+	db.remove(&sibling);
+	let mut lazy = MPT::with_source(root, db);
+	assert_eq!(lazy.get(vec![0x20]), Err(MptError::MissingNode(sibling)));
+	assert_eq!(lazy.insert(vec![0x21], vec![3]), Err(MptError::MissingNode(sibling)));
+	// Removing the other key would merge the sibling into the root.
+	assert_eq!(lazy.remove(vec![0x10]), Err(MptError::MissingNode(sibling)));
+	assert_eq!(lazy.hash(), root);
+	assert_eq!(lazy.get(vec![0x10]).unwrap(), Some(&[1; 40][..]));
+	assert_eq!(lazy.iter().last(), Some(Err(MptError::MissingNode(sibling))));
+	assert!(MPT::from_db(root, lazy.db.nodes.clone()).is_err());
+
+	let mut empty = MPT::with_source(root, HashMap::new());
+	assert_eq!(empty.get(vec![0x10]), Err(MptError::MissingNode(root)));
+	assert_eq!(empty.prove(vec![0x10]), Err(MptError::MissingNode(root)));
+	assert_eq!(empty.diff(&mut MPT::default()), Err(MptError::MissingNode(root)));
+
+	// A root with an invalid compact path.
+	let bad_path = vec![0xc4, 0x82, 0x40, 0x12, 0x01];
+	let root = keccak(&bad_path);
+	let mpt = MPT::with_source(root, HashMap::from([(root, bad_path)]));
+	assert_eq!(
+		mpt.get(vec![0x10]),
+		Err(MptError::InvalidNode(reth_rlp::DecodeError::Custom("invalid compact path")))
+	);
+
+	// An extension that points to a leaf by hash.
+	let leaf = [&[0xec, 0x82, 0x20, 0x34, 0xa8][..], &[0xaa; 40]].concat();
+	let extension = [&[0xe4, 0x82, 0x00, 0x12, 0xa0][..], &keccak(&leaf).to_vec()].concat();
+	let root = keccak(&extension);
+	let db = HashMap::from([(root, extension), (keccak(&leaf), leaf)]);
+	let mut mpt = MPT::with_source(root, db.clone());
+	assert_eq!(mpt.get(vec![0x12, 0x34]), Err(MptError::InvalidExtension));
+	assert_eq!(mpt.insert(vec![0x12, 0x35], vec![1]), Err(MptError::InvalidExtension));
+	assert_eq!(mpt.remove(vec![0x12, 0x34]), Err(MptError::InvalidExtension));
+	assert_eq!(mpt.hash(), root);
+	assert_eq!(MPT::from_db(root, db).err(), Some(MptError::InvalidExtension));
+}
+
+#[test]
+fn test_decode_malformed_nodes() {
+	use crate::decode::{decode_bytes, decode_list};
+
+	// Items whose headers claim more bytes than are left.
+	let nodes = [
+		vec![0xc2, 0x85, 0x00],
+		vec![0xc3, 0x20, 0x01, 0x85],
+		vec![0xc4, 0xb8, 0xff, 0x00, 0x01],
+		vec![0xc2, 0xc5, 0x01],
+	];
+	for node in nodes {
+		assert!(decode_list(&node).is_err(), "{node:x?}");
+		assert!(Node::decode(&node).is_err(), "{node:x?}");
+		assert!(Account::decode(&node).is_err(), "{node:x?}");
+		let root = keccak(&node);
+		let db = HashMap::from([(root, node.clone())]);
+		assert!(matches!(
+			MPT::with_source(root, db.clone()).get(vec![0x20]),
+			Err(MptError::InvalidNode(_))
+		));
+		assert!(matches!(MPT::from_db(root, db), Err(MptError::InvalidNode(_))));
+		assert!(verify_proof(root, &[0x20], &[node]).is_err());
+	}
+	assert_eq!(decode_bytes(&[0x85, 0x01]), Err(reth_rlp::DecodeError::InputTooShort));
+}
+
+#[test]
+fn test_mpt_hash_mismatch() {
+	let mut mpt = MPT::default();
+	mpt.insert(vec![0x10], vec![1; 40]).unwrap();
+	mpt.insert(vec![0x20], vec![2; 40]).unwrap();
+	let root = mpt.hash();
+	let sibling = keccak(&mpt.prove(vec![0x20]).unwrap()[1]);
+
+	// The source returns a valid node, but not the one that was requested.
+	let mut other = MPT::default();
+	other.insert(vec![0x20], vec![3; 40]).unwrap();
+	let mut db = mpt.db.nodes.clone();
+	db.insert(sibling, other.prove(vec![0x20]).unwrap().remove(0));
+	let lazy = MPT::with_source(root, db.clone());
+	assert_eq!(lazy.get(vec![0x20]), Err(MptError::HashMismatch(sibling)));
+	assert_eq!(lazy.get(vec![0x10]).unwrap(), Some(&[1; 40][..]));
+	assert_eq!(MPT::from_db(root, db).err(), Some(MptError::HashMismatch(sibling)));
+}
@@ -11,8 +11,15 @@ fn main() -> Result<()> {
 	let provider = std::env::var("RPC")?;
 	let mut provider = Client::new(&provider)?;
 
+	let l2_provider = std::env::var("L2_RPC")?;
+	let mut l2_provider = Client::new(&l2_provider)?;
+
 	let mut derivation = Derivation::new(core::chain_config::GOERLI_CONFIG);
-	derivation.run(8300532, 8300533, &mut provider);
+	// Blocks are not executed yet, so the hash of each derived block is read from the L2 chain.
+	derivation.run(8300532, 8300533, &mut provider, |l2_head, _| {
+		let header = l2_provider.get_header_by_number(l2_head.number + 1)?;
+		Ok(header.hash_slow().into())
+	})?;
 
 	Ok(())
 }